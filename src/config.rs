@@ -0,0 +1,273 @@
+use crate::model::{Function, Profile};
+use crate::settings::{
+    validate_idle_seconds, validate_low_battery_percent, DpiStages, PollingRate, SettingError,
+};
+use ron::extensions::Extensions;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+#[derive(Debug)]
+pub(crate) enum ConfigError {
+    Io(io::Error),
+    Ron(ron::error::SpannedError),
+    RonWrite(ron::Error),
+    Json(serde_json::Error),
+    Profile(u8),
+    Setting(SettingError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Ron(e) => write!(f, "Invalid RON: {}", e),
+            ConfigError::RonWrite(e) => write!(f, "Unable to write RON: {}", e),
+            ConfigError::Json(e) => write!(f, "Invalid JSON: {}", e),
+            ConfigError::Profile(n) => write!(
+                f,
+                "There's no profile {}, the mouse only has slots 1 to {}",
+                n,
+                Profile::SLOTS
+            ),
+            ConfigError::Setting(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+/// Files ending in .json are JSON, everything else is assumed to be RON.
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Everything a config file can hold. A file with nothing but a list of functions in it is read as those functions going
+/// to the default profile.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct Config {
+    /// Which onboard profile the functions are assigned in.
+    #[serde(default)]
+    pub(crate) profile: Profile,
+    #[serde(default)]
+    pub(crate) functions: Vec<Function>,
+    /// Left alone if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dpi_stages: Option<DpiStages>,
+    /// In Hz, left alone if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) polling_rate: Option<PollingRate>,
+    /// How long the mouse waits before going to sleep, left alone if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) idle_seconds: Option<u16>,
+    /// Battery percentage the mouse goes into low power mode at, left alone if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) low_battery_percent: Option<u8>,
+}
+
+/// Whether the file is just a list of functions rather than a whole `Config`. Goes by the first thing in the file that
+/// isn't a comment or a RON extension attribute.
+fn is_list(data: &str) -> bool {
+    data.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("#!") && !line.starts_with("//"))
+        .is_some_and(|line| line.starts_with('['))
+}
+
+fn parse<T: serde::de::DeserializeOwned>(data: &str, json: bool) -> Result<T, ConfigError> {
+    if json {
+        serde_json::from_str(data).map_err(ConfigError::Json)
+    } else {
+        ron::from_str(data).map_err(ConfigError::Ron)
+    }
+}
+
+pub(crate) fn load_config(path: &Path) -> Result<Config, ConfigError> {
+    let mut data = String::new();
+    let f = File::open(path)?;
+    let mut br = BufReader::new(f);
+    br.read_to_string(&mut data)?;
+    let config = if is_list(&data) {
+        Config {
+            functions: parse(&data, is_json(path))?,
+            ..Config::default()
+        }
+    } else {
+        parse(&data, is_json(path))?
+    };
+    validate(&config)?;
+    Ok(config)
+}
+
+/// Catches whatever the types alone let through.
+fn validate(config: &Config) -> Result<(), ConfigError> {
+    if let Profile::Slot(n) = config.profile {
+        // Slot 0 would be sent as 0x00, which is volatile.
+        if !(1..=Profile::SLOTS).contains(&n) {
+            return Err(ConfigError::Profile(n));
+        }
+    }
+    if let Some(stages) = &config.dpi_stages {
+        stages.validate().map_err(ConfigError::Setting)?;
+    }
+    if let Some(seconds) = config.idle_seconds {
+        validate_idle_seconds(seconds).map_err(ConfigError::Setting)?;
+    }
+    if let Some(percent) = config.low_battery_percent {
+        validate_low_battery_percent(percent).map_err(ConfigError::Setting)?;
+    }
+    Ok(())
+}
+
+/// The same layout funcs.ron is written in, so a dump can be read straight back in.
+pub(crate) fn to_ron<T: Serialize + ?Sized>(value: &T) -> Result<String, ConfigError> {
+    let ron_pretty = PrettyConfig::new()
+        .indentor("  ".into())
+        .new_line("\n".into())
+        .compact_arrays(true)
+        .separate_tuple_members(false)
+        .extensions(Extensions::UNWRAP_VARIANT_NEWTYPES);
+    ron::ser::to_string_pretty(value, ron_pretty).map_err(ConfigError::RonWrite)
+}
+
+pub(crate) fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, ConfigError> {
+    serde_json::to_string_pretty(value).map_err(ConfigError::Json)
+}
+
+pub(crate) fn save_config(path: &Path, config: &Config) -> Result<(), ConfigError> {
+    write(path, &config_to_string(config, is_json(path))?)
+}
+
+/// A plain list of functions if that's all there is to `config`, so converting an old style config doesn't turn it
+/// into the struct form.
+pub(crate) fn config_to_string(config: &Config, json: bool) -> Result<String, ConfigError> {
+    let just_functions = Config {
+        functions: config.functions.clone(),
+        ..Config::default()
+    };
+    if *config == just_functions {
+        serialize(&config.functions, json)
+    } else {
+        serialize(config, json)
+    }
+}
+
+fn serialize<T: Serialize + ?Sized>(value: &T, json: bool) -> Result<String, ConfigError> {
+    if json {
+        to_json(value)
+    } else {
+        to_ron(value)
+    }
+}
+
+fn write(path: &Path, data: &str) -> Result<(), ConfigError> {
+    let f = File::create(path)?;
+    let mut f = BufWriter::new(f);
+    f.write_all(data.as_bytes())?;
+    f.write_all(b"\n")?;
+    f.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config_to_string, is_list, parse, to_json, to_ron, validate, Config, ConfigError};
+    use crate::model::{funcs_ron, Function, Profile};
+    use crate::settings::{Dpi, DpiStages, PollingRate};
+
+    #[test]
+    fn ron_round_trip() {
+        let examples = include_str!("../examples.ron");
+        let funcs: Vec<Function> = ron::from_str(examples).unwrap();
+        let written = to_ron(&funcs).unwrap();
+        assert!(written.starts_with("#![enable(unwrap_variant_newtypes)]"));
+        assert_eq!(ron::from_str::<Vec<Function>>(&written).unwrap(), funcs);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let funcs = funcs_ron();
+        let written = to_json(&funcs).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<Function>>(&written).unwrap(),
+            funcs
+        );
+    }
+
+    #[test]
+    fn profile_config() {
+        let written =
+            "(\n  profile: Slot(3),\n  functions: [(button: Side1, action: hypershift)],\n)";
+        assert!(!is_list(written));
+        let config: Config = parse(written, false).unwrap();
+        assert_eq!(config.profile, Profile::Slot(3));
+        assert_eq!(config.functions.len(), 1);
+
+        assert!(is_list(include_str!("../funcs.ron")));
+        let config: Config = parse("(functions: [])", false).unwrap();
+        assert_eq!(config.profile, Profile::default());
+        let config: Config = parse(r#"{"profile": "Volatile"}"#, true).unwrap();
+        assert_eq!(config.profile, Profile::Volatile);
+        assert!(validate(&config).is_ok());
+
+        for slot in [0, Profile::SLOTS + 1] {
+            let config: Config = parse(&format!("(profile: Slot({}))", slot), false).unwrap();
+            assert!(matches!(validate(&config), Err(ConfigError::Profile(n)) if n == slot));
+        }
+    }
+
+    #[test]
+    fn dpi_stages_config() {
+        let written =
+            "(dpi_stages: Some((active: 1, stages: [(x: 800, y: 800), (x: 1600, y: 1600)])))";
+        let config: Config = parse(written, false).unwrap();
+        assert_eq!(
+            config.dpi_stages,
+            Some(DpiStages {
+                active: 1,
+                stages: vec![Dpi { x: 800, y: 800 }, Dpi { x: 1600, y: 1600 }],
+            })
+        );
+        assert!(config.functions.is_empty());
+        assert_eq!(parse::<Config>("()", false).unwrap().dpi_stages, None);
+
+        let config: Config = parse(r#"{"polling_rate": 500}"#, true).unwrap();
+        assert_eq!(config.polling_rate, Some(PollingRate::Hz500));
+        assert!(parse::<Config>("(polling_rate: Some(300))", false).is_err());
+    }
+
+    #[test]
+    fn convert_keeps_form() {
+        let funcs = funcs_ron();
+        let config = Config {
+            functions: funcs.clone(),
+            ..Config::default()
+        };
+        for json in [false, true] {
+            let written = config_to_string(&config, json).unwrap();
+            assert!(is_list(&written));
+            assert_eq!(parse::<Vec<Function>>(&written, json).unwrap(), funcs);
+        }
+
+        let config = Config {
+            profile: Profile::Slot(2),
+            polling_rate: Some(PollingRate::Hz500),
+            ..config
+        };
+        for json in [false, true] {
+            let written = config_to_string(&config, json).unwrap();
+            assert!(!is_list(&written));
+            assert_eq!(parse::<Config>(&written, json).unwrap(), config);
+        }
+    }
+}
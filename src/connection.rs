@@ -0,0 +1,91 @@
+use hidapi::BusType;
+use std::fmt;
+
+pub(crate) const RAZER_VENDOR_ID: u16 = 0x1532;
+
+/// How the mouse is attached. Each way shows up under its own product ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Connection {
+    /// The 2.4 GHz HyperSpeed dongle.
+    Dongle,
+    Wired,
+    Bluetooth,
+}
+
+impl Connection {
+    pub(crate) const ALL: [Connection; 3] =
+        [Connection::Dongle, Connection::Wired, Connection::Bluetooth];
+
+    /// The product ID the mouse shows up with this way, if known. Only the dongle's is so far. The others are
+    /// placeholders until someone finds out what they are, and have to be given with `--product-id` meanwhile.
+    pub(crate) fn product_id(self) -> Option<u16> {
+        match self {
+            Connection::Dongle => Some(0x00b4),
+            Connection::Wired | Connection::Bluetooth => None,
+        }
+    }
+
+    /// Works out how a razer device is attached from its bus type and product ID, or `None` if it isn't the naga.
+    /// `extra_product_id` is taken to be the naga too, for ways of attaching it whose product ID isn't known yet.
+    pub(crate) fn detect(
+        bus: BusType,
+        product_id: u16,
+        extra_product_id: Option<u16>,
+    ) -> Option<Connection> {
+        let known = Connection::ALL
+            .iter()
+            .any(|connection| connection.product_id() == Some(product_id));
+        if !known && extra_product_id != Some(product_id) {
+            return None;
+        }
+        match bus {
+            BusType::Bluetooth => Some(Connection::Bluetooth),
+            _ if Connection::Dongle.product_id() == Some(product_id) => Some(Connection::Dongle),
+            // Anything else has to be the cable. Some platforms don't say what bus a device is on, but it was asked for
+            // by product ID, so it's taken to be the naga all the same.
+            _ => Some(Connection::Wired),
+        }
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Connection::Dongle => write!(f, "HyperSpeed dongle"),
+            Connection::Wired => write!(f, "USB cable"),
+            Connection::Bluetooth => write!(f, "Bluetooth"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Connection;
+    use hidapi::BusType;
+
+    #[test]
+    fn detect() {
+        assert_eq!(
+            Connection::detect(BusType::Usb, 0x00b4, None),
+            Some(Connection::Dongle)
+        );
+        // Some platforms don't say what bus a device is on, the dongle is still recognisable by its product ID.
+        assert_eq!(
+            Connection::detect(BusType::Unknown, 0x00b4, None),
+            Some(Connection::Dongle)
+        );
+        assert_eq!(Connection::detect(BusType::Usb, 0x00b5, None), None);
+        assert_eq!(
+            Connection::detect(BusType::Usb, 0x00b5, Some(0x00b5)),
+            Some(Connection::Wired)
+        );
+        assert_eq!(
+            Connection::detect(BusType::Bluetooth, 0x00b5, Some(0x00b5)),
+            Some(Connection::Bluetooth)
+        );
+        assert_eq!(
+            Connection::detect(BusType::Unknown, 0x00b5, Some(0x00b5)),
+            Some(Connection::Wired)
+        );
+    }
+}
@@ -0,0 +1,1229 @@
+use crate::diff::{self, BindingChange};
+use crate::model::{Action, DecodeError, Function, Layer, MouseButton, Profile};
+use crate::report::{RazerReport, ReportError, ReportStatus, REPORT_LEN};
+use crate::settings::{
+    percent_from_raw, raw_from_percent, Battery, DeviceMode, Dpi, DpiStages, FirmwareVersion,
+    PollingRate,
+};
+use crate::transport::FeatureReportDevice;
+use hidapi::HidError;
+use std::{
+    fmt,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+pub(crate) enum CommandError {
+    /// The device wouldn't even take the report.
+    Send(HidError),
+    /// The report went out but reading the response back failed.
+    Receive(HidError),
+    /// The response couldn't be made sense of.
+    Report(ReportError),
+    /// The mouse answered with anything but success.
+    Status(ReportStatus),
+    /// The response was fine as a report, but what it carried didn't make sense.
+    Decode(DecodeError),
+    /// The response didn't echo back the command that was sent.
+    Mismatch {
+        sent: Box<RazerReport>,
+        received: Box<RazerReport>,
+    },
+    /// The mouse said it took a setting, but reading it back says otherwise.
+    NotApplied { wanted: String, actual: String },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Send(e) => write!(f, "Failed to send message: {}", e),
+            CommandError::Receive(e) => write!(f, "Failed to read response: {}", e),
+            CommandError::Report(e) => write!(f, "Invalid response: {}", e),
+            CommandError::Status(status) => write!(f, "Failed to send message: {}.", status),
+            CommandError::Decode(e) => write!(f, "Couldn't make sense of the response: {}", e),
+            CommandError::NotApplied { wanted, actual } => write!(
+                f,
+                "The mouse accepted {} but is still at {}",
+                wanted, actual
+            ),
+            CommandError::Mismatch { sent, received } => {
+                match (sent.to_function(), received.to_function()) {
+                    (Ok(sent_func), Ok(received_func)) if !sent.is_read() => write!(
+                        f,
+                        "Response doesn't match what was sent: sent {:?}, got {:?}",
+                        sent_func, received_func
+                    ),
+                    _ => write!(
+                        f,
+                        "Response doesn't match what was sent: sent class {:#04x} id {:#04x} {:02x?}, got class {:#04x} id {:#04x} {:02x?}",
+                        sent.command_class,
+                        sent.command_id,
+                        sent.args(),
+                        received.command_class,
+                        received.command_id,
+                        received.args()
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// How hard to try getting a report through before giving up on it.
+#[derive(Clone, Debug)]
+pub(crate) struct RetryPolicy {
+    /// How many times to resend a report that didn't go through.
+    pub(crate) retries: u32,
+    /// Delay before the first resend, doubled for every one after that (up to `MAX_BACKOFF`.)
+    pub(crate) backoff: Duration,
+    /// How many times to re-read the response while the mouse says it's still busy with the command.
+    pub(crate) busy_polls: u32,
+    pub(crate) busy_interval: Duration,
+    /// If set, a mouse that's asleep gets resent the report for up to this long, regardless of `retries`. Gives you a
+    /// chance to wiggle it awake.
+    pub(crate) wait_for_wake: Option<Duration>,
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff: Duration::from_millis(50),
+            busy_polls: 20,
+            busy_interval: Duration::from_millis(10),
+            wait_for_wake: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl RetryPolicy {
+    /// Retries without waiting in between, so tests don't sit through the backoff.
+    pub(crate) fn no_wait(retries: u32) -> Self {
+        Self {
+            retries,
+            backoff: Duration::ZERO,
+            busy_interval: Duration::ZERO,
+            ..Self::default()
+        }
+    }
+}
+
+impl CommandError {
+    /// Whether sending the same report again has a chance of going better.
+    fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            CommandError::Send(_)
+                | CommandError::Status(ReportStatus::NotSupported)
+                | CommandError::Decode(_)
+        )
+    }
+}
+
+/// Sends a report and reads the response once, polling for as long as the mouse says it's busy.
+fn exchange<D: FeatureReportDevice>(
+    device: &D,
+    request: &RazerReport,
+    policy: &RetryPolicy,
+) -> Result<RazerReport, CommandError> {
+    device
+        .send_feature_report(&request.to_bytes())
+        .map_err(CommandError::Send)?;
+
+    let mut polls = 0;
+    loop {
+        let mut buf = [0u8; REPORT_LEN];
+        device
+            .get_feature_report(&mut buf)
+            .map_err(CommandError::Receive)?;
+        let response = RazerReport::parse(&buf).map_err(CommandError::Report)?;
+        match response.status {
+            ReportStatus::Success => {}
+            ReportStatus::Busy | ReportStatus::New if polls < policy.busy_polls => {
+                polls += 1;
+                sleep(policy.busy_interval);
+                continue;
+            }
+            status => return Err(CommandError::Status(status)),
+        }
+        if !request.answered_by(&response) {
+            return Err(CommandError::Mismatch {
+                sent: Box::new(request.clone()),
+                received: Box::new(response),
+            });
+        }
+        return Ok(response);
+    }
+}
+
+/// Sends a report and returns the mouse's response, retrying according to the policy.
+pub(crate) fn transact<D: FeatureReportDevice>(
+    device: &D,
+    request: &RazerReport,
+    policy: &RetryPolicy,
+) -> Result<RazerReport, CommandError> {
+    let started = Instant::now();
+    let mut attempt = 0;
+    let mut delay = policy.backoff;
+    loop {
+        let error = match exchange(device, request, policy) {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+        let waiting_for_wake = matches!(error, CommandError::Status(ReportStatus::Timeout))
+            && policy
+                .wait_for_wake
+                .is_some_and(|wait| started.elapsed() < wait);
+        if !error.is_retryable() || (attempt >= policy.retries && !waiting_for_wake) {
+            return Err(error);
+        }
+        attempt += 1;
+        sleep(delay);
+        delay = (delay * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Reads back what a button is currently assigned to on one layer.
+pub(crate) fn get_binding<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    (button, layer): (MouseButton, Layer),
+    policy: &RetryPolicy,
+) -> Result<Function, CommandError> {
+    let request = RazerReport::get_binding(button, layer, profile);
+    let response = transact(device, &request, policy)?;
+    let func = response.to_function().map_err(CommandError::Decode)?;
+    if func.key() != (button, layer) {
+        return Err(CommandError::Mismatch {
+            sent: Box::new(request),
+            received: Box::new(response),
+        });
+    }
+    Ok(func)
+}
+
+/// Reads back what each of the buttons is currently assigned to on the given layers.
+pub(crate) fn get_bindings<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    keys: &[(MouseButton, Layer)],
+    policy: &RetryPolicy,
+) -> Result<Vec<Function>, CommandError> {
+    keys.iter()
+        .map(|key| get_binding(device, profile, *key, policy))
+        .collect()
+}
+
+/// Every button's base assignment, plus whatever it does while Hypershift is held if that's anything at all. A button
+/// that is the Hypershift key only shows up as that, rather than also as disabled on the base layer.
+pub(crate) fn all_bindings<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    policy: &RetryPolicy,
+) -> Result<Vec<Function>, CommandError> {
+    let keys: Vec<_> = Layer::ALL
+        .into_iter()
+        .flat_map(|layer| MouseButton::ALL.map(|button| (button, layer)))
+        .collect();
+    let bindings = get_bindings(device, profile, &keys, policy)?;
+    let (base, shifted) = bindings.split_at(MouseButton::ALL.len());
+    let is_hypershift_key = |button| {
+        shifted
+            .iter()
+            .any(|func| func.button == button && matches!(func.action, Action::Hypershift))
+    };
+    Ok(base
+        .iter()
+        .filter(|func| !matches!(func.action, Action::Disable) || !is_hypershift_key(func.button))
+        .chain(
+            shifted
+                .iter()
+                .filter(|func| !matches!(func.action, Action::Disable)),
+        )
+        .cloned()
+        .collect())
+}
+
+pub(crate) fn get_firmware_version<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<FirmwareVersion, CommandError> {
+    let response = transact(device, &RazerReport::get_firmware_version(), policy)?;
+    Ok(FirmwareVersion {
+        major: response.arguments[0],
+        minor: response.arguments[1],
+    })
+}
+
+pub(crate) fn get_serial<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<String, CommandError> {
+    let response = transact(device, &RazerReport::get_serial(), policy)?;
+    let serial = &response.arguments[..22];
+    let len = serial.iter().position(|b| *b == 0).unwrap_or(serial.len());
+    Ok(String::from_utf8_lossy(&serial[..len]).into_owned())
+}
+
+pub(crate) fn get_device_mode<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<DeviceMode, CommandError> {
+    let response = transact(device, &RazerReport::get_device_mode(), policy)?;
+    Ok(DeviceMode::from(response.arguments[0]))
+}
+
+/// Battery level and whether it's charging. Takes two reports, there doesn't seem to be one that has both.
+pub(crate) fn get_battery<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<Battery, CommandError> {
+    let level = transact(device, &RazerReport::get_battery_level(), policy)?.arguments[1];
+    let charging = transact(device, &RazerReport::get_charging_status(), policy)?.arguments[1];
+    Ok(Battery::from_raw(level, charging != 0))
+}
+
+/// How many seconds the mouse waits before going to sleep.
+pub(crate) fn get_idle_seconds<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<u16, CommandError> {
+    let response = transact(device, &RazerReport::get_idle_time(), policy)?;
+    Ok(u16::from_be_bytes([
+        response.arguments[0],
+        response.arguments[1],
+    ]))
+}
+
+pub(crate) fn set_idle_seconds<D: FeatureReportDevice>(
+    device: &D,
+    seconds: u16,
+    policy: &RetryPolicy,
+) -> Result<(), CommandError> {
+    transact(device, &RazerReport::set_idle_time(seconds), policy).map(|_| ())
+}
+
+/// The battery percentage the mouse goes into low power mode at.
+pub(crate) fn get_low_battery_percent<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<u8, CommandError> {
+    let response = transact(device, &RazerReport::get_low_battery_threshold(), policy)?;
+    Ok(percent_from_raw(response.arguments[0]))
+}
+
+pub(crate) fn set_low_battery_percent<D: FeatureReportDevice>(
+    device: &D,
+    percent: u8,
+    policy: &RetryPolicy,
+) -> Result<(), CommandError> {
+    let request = RazerReport::set_low_battery_threshold(raw_from_percent(percent));
+    transact(device, &request, policy).map(|_| ())
+}
+
+pub(crate) fn get_polling_rate<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<PollingRate, CommandError> {
+    transact(device, &RazerReport::get_polling_rate(), policy)?
+        .to_polling_rate()
+        .map_err(CommandError::Decode)
+}
+
+/// Changes the polling rate and reads it back to make sure it took.
+pub(crate) fn set_polling_rate<D: FeatureReportDevice>(
+    device: &D,
+    rate: PollingRate,
+    policy: &RetryPolicy,
+) -> Result<(), CommandError> {
+    transact(device, &RazerReport::set_polling_rate(rate), policy)?;
+    let actual = get_polling_rate(device, policy)?;
+    if actual != rate {
+        return Err(CommandError::NotApplied {
+            wanted: rate.to_string(),
+            actual: actual.to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn get_dpi<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    policy: &RetryPolicy,
+) -> Result<Dpi, CommandError> {
+    transact(device, &RazerReport::get_dpi(profile), policy)?
+        .to_dpi()
+        .map_err(CommandError::Decode)
+}
+
+/// Changes the sensitivity right away. Whatever stage the mouse is on stays the same, so the next stage button press
+/// goes back to the stage table.
+pub(crate) fn set_dpi<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    dpi: Dpi,
+    policy: &RetryPolicy,
+) -> Result<(), CommandError> {
+    transact(device, &RazerReport::set_dpi(dpi, profile), policy).map(|_| ())
+}
+
+pub(crate) fn get_dpi_stages<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    policy: &RetryPolicy,
+) -> Result<DpiStages, CommandError> {
+    transact(device, &RazerReport::get_dpi_stages(profile), policy)?
+        .to_dpi_stages()
+        .map_err(CommandError::Decode)
+}
+
+pub(crate) fn set_dpi_stages<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    stages: &DpiStages,
+    policy: &RetryPolicy,
+) -> Result<(), CommandError> {
+    transact(
+        device,
+        &RazerReport::set_dpi_stages(stages, profile),
+        policy,
+    )
+    .map(|_| ())
+}
+
+/// Which onboard profile the mouse is currently using.
+pub(crate) fn get_active_profile<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<Profile, CommandError> {
+    transact(device, &RazerReport::get_active_profile(), policy)?
+        .to_active_profile()
+        .map_err(CommandError::Decode)
+}
+
+pub(crate) fn set_active_profile<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    policy: &RetryPolicy,
+) -> Result<(), CommandError> {
+    transact(device, &RazerReport::set_active_profile(profile), policy).map(|_| ())
+}
+
+/// The onboard slots the mouse actually has. There doesn't seem to be a report that says so outright, so this asks each
+/// slot for a binding and keeps the ones that answer.
+pub(crate) fn available_profiles<D: FeatureReportDevice>(
+    device: &D,
+    policy: &RetryPolicy,
+) -> Result<Vec<Profile>, CommandError> {
+    let mut profiles = vec![];
+    for slot in 1..=Profile::SLOTS {
+        let profile = Profile::Slot(slot);
+        match get_binding(device, profile, (MouseButton::LClick, Layer::Base), policy) {
+            Ok(_) => profiles.push(profile),
+            Err(CommandError::Status(ReportStatus::Failure | ReportStatus::NotSupported)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(profiles)
+}
+
+/// The functions that would actually change something on the mouse. Only the last assignment of each button is kept,
+/// since that's what the button would end up with anyway.
+pub(crate) fn changed_functions<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    functions: &[Function],
+    policy: &RetryPolicy,
+) -> Result<Vec<Function>, CommandError> {
+    let current = get_bindings(device, profile, &diff::keys(functions), policy)?;
+    Ok(diff::diff(&current, functions)
+        .into_iter()
+        .filter_map(|change| match change {
+            BindingChange::Unchanged(_) => None,
+            BindingChange::Added(wanted) | BindingChange::Changed { wanted, .. } => {
+                Some(wanted.clone())
+            }
+        })
+        .collect())
+}
+
+/// A button that reads back differently from what was applied to it.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Mismatch {
+    pub(crate) wanted: Function,
+    pub(crate) actual: Function,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} didn't take, the mouse has {} instead",
+            self.wanted, self.actual.action
+        )
+    }
+}
+
+/// Reads back every button the functions touch, and returns the ones that don't match what they should be.
+pub(crate) fn verify_functions<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    functions: &[Function],
+    policy: &RetryPolicy,
+) -> Result<Vec<Mismatch>, CommandError> {
+    let current = get_bindings(device, profile, &diff::keys(functions), policy)?;
+    Ok(diff::diff(&current, functions)
+        .into_iter()
+        .filter_map(|change| match change {
+            BindingChange::Unchanged(_) => None,
+            BindingChange::Added(wanted) => Some(Mismatch {
+                wanted: wanted.clone(),
+                actual: Function {
+                    button: wanted.button,
+                    layer: wanted.layer,
+                    action: Action::Disable,
+                },
+            }),
+            BindingChange::Changed { current, wanted } => Some(Mismatch {
+                wanted: wanted.clone(),
+                actual: current.clone(),
+            }),
+        })
+        .collect())
+}
+
+/// Why a transactional apply was rolled back.
+#[derive(Debug)]
+pub(crate) enum RollbackCause {
+    Apply(Vec<FailedFunction>),
+    Verify(Vec<Mismatch>),
+    VerifyRead(CommandError),
+}
+
+#[derive(Debug)]
+pub(crate) enum TransactionError {
+    /// The current bindings couldn't be read, so nothing was touched.
+    Snapshot(CommandError),
+    /// Something went wrong, so the snapshot was put back. `rollback` is how that went.
+    RolledBack {
+        cause: RollbackCause,
+        rollback: Result<(), Vec<FailedFunction>>,
+    },
+}
+
+/// Applies the functions as all or nothing: whatever the touched buttons are assigned to gets read first, and if any of
+/// the functions fail (or don't read back right, when verifying) those assignments are put back.
+pub(crate) fn apply_transaction<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    functions: &[Function],
+    policy: &RetryPolicy,
+    verify: bool,
+) -> Result<(), TransactionError> {
+    let snapshot = get_bindings(device, profile, &diff::keys(functions), policy)
+        .map_err(TransactionError::Snapshot)?;
+
+    let cause = match apply_functions(device, profile, functions, policy) {
+        Err(failures) => RollbackCause::Apply(failures),
+        Ok(()) if !verify => return Ok(()),
+        Ok(()) => match verify_functions(device, profile, functions, policy) {
+            Ok(mismatches) if mismatches.is_empty() => return Ok(()),
+            Ok(mismatches) => RollbackCause::Verify(mismatches),
+            Err(e) => RollbackCause::VerifyRead(e),
+        },
+    };
+    Err(TransactionError::RolledBack {
+        cause,
+        rollback: apply_functions(device, profile, &snapshot, policy),
+    })
+}
+
+/// A function from the list that couldn't be applied, and its position in the list.
+#[derive(Debug)]
+pub(crate) struct FailedFunction {
+    pub(crate) index: usize,
+    pub(crate) function: Function,
+    pub(crate) error: CommandError,
+}
+
+impl fmt::Display for FailedFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.function, self.error)
+    }
+}
+
+/// Sends every function to the device in order. Anything that still fails after retrying is recorded and the rest are
+/// applied anyway, unless the device refuses to take reports at all, in which case there's no point carrying on.
+pub(crate) fn apply_functions<D: FeatureReportDevice>(
+    device: &D,
+    profile: Profile,
+    functions: &[Function],
+    policy: &RetryPolicy,
+) -> Result<(), Vec<FailedFunction>> {
+    let mut failures = vec![];
+    for (index, func) in functions.iter().enumerate() {
+        if let Err(error) = transact(device, &RazerReport::from_function(func, profile), policy) {
+            let fatal = matches!(error, CommandError::Send(_));
+            failures.push(FailedFunction {
+                index,
+                function: func.clone(),
+                error,
+            });
+            if fatal {
+                break;
+            }
+        }
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        all_bindings, apply_functions, apply_transaction, available_profiles, changed_functions,
+        get_active_profile, get_battery, get_binding, get_device_mode, get_dpi, get_dpi_stages,
+        get_firmware_version, get_idle_seconds, get_low_battery_percent, get_polling_rate,
+        get_serial, set_active_profile, set_dpi, set_dpi_stages, set_idle_seconds,
+        set_low_battery_percent, set_polling_rate, verify_functions, CommandError, FailedFunction,
+        Mismatch, RetryPolicy, RollbackCause, TransactionError,
+    };
+    use crate::emulator::{EmulatedNaga, SERIAL};
+    use crate::model::{
+        disable, funcs_ron, generate_message, Action, Function, Layer, MouseButton, Profile,
+    };
+    use crate::report::ReportStatus;
+    use crate::settings::{DeviceMode, Dpi, DpiStages, PollingRate};
+    use crate::transport::mock::MockDevice;
+    use std::time::Duration;
+
+    fn response(func: &Function, status: ReportStatus) -> [u8; 91] {
+        let mut response = generate_message(func);
+        response[1] = status as u8;
+        response
+    }
+
+    /// An emulated naga with `funcs` already applied to `profile`.
+    fn naga_with(profile: Profile, funcs: &[Function]) -> EmulatedNaga {
+        let naga = EmulatedNaga::new();
+        apply_functions(&naga, profile, funcs, &RetryPolicy::no_wait(0)).unwrap();
+        naga
+    }
+
+    #[test]
+    fn apply_sends_every_function() {
+        let funcs = vec![disable(MouseButton::Side1), disable(MouseButton::Side2)];
+        let device = MockDevice::new();
+        for func in &funcs {
+            device.push_response(&response(func, ReportStatus::Success));
+        }
+
+        apply_functions(
+            &device,
+            Profile::default(),
+            &funcs,
+            &RetryPolicy::no_wait(0),
+        )
+        .unwrap();
+
+        let sent = device.sent.borrow();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], generate_message(&funcs[0]));
+        assert_eq!(sent[1], generate_message(&funcs[1]));
+    }
+
+    #[test]
+    fn apply_reports_each_failure() {
+        let funcs = vec![disable(MouseButton::Side1), disable(MouseButton::Side2)];
+        let device = MockDevice::new();
+        device.push_response(&response(&funcs[0], ReportStatus::Timeout));
+        device.push_response(&response(&funcs[1], ReportStatus::Success));
+
+        let failures = apply_functions(
+            &device,
+            Profile::default(),
+            &funcs,
+            &RetryPolicy::no_wait(0),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            failures.as_slice(),
+            [FailedFunction {
+                index: 0,
+                error: CommandError::Status(ReportStatus::Timeout),
+                ..
+            }]
+        ));
+        assert_eq!(device.sent.borrow().len(), 2);
+    }
+
+    #[test]
+    fn apply_retries_until_success() {
+        let func = disable(MouseButton::Side1);
+        let device = MockDevice::new();
+        device.push_response(&response(&func, ReportStatus::Timeout));
+        device.push_response(&response(&func, ReportStatus::Failure));
+        device.push_response(&response(&func, ReportStatus::Success));
+
+        apply_functions(
+            &device,
+            Profile::default(),
+            &[func],
+            &RetryPolicy::no_wait(2),
+        )
+        .unwrap();
+        assert_eq!(device.sent.borrow().len(), 3);
+    }
+
+    #[test]
+    fn apply_polls_while_busy() {
+        let func = disable(MouseButton::Side1);
+        let device = MockDevice::new();
+        device.push_response(&response(&func, ReportStatus::Busy));
+        device.push_response(&response(&func, ReportStatus::Busy));
+        device.push_response(&response(&func, ReportStatus::Success));
+
+        apply_functions(
+            &device,
+            Profile::default(),
+            &[func],
+            &RetryPolicy::no_wait(0),
+        )
+        .unwrap();
+        assert_eq!(device.sent.borrow().len(), 1);
+    }
+
+    #[test]
+    fn apply_reports_not_supported() {
+        let func = disable(MouseButton::Side1);
+        let device = MockDevice::new();
+        device.push_response(&response(&func, ReportStatus::NotSupported));
+
+        let failures = apply_functions(
+            &device,
+            Profile::default(),
+            &[func],
+            &RetryPolicy::no_wait(3),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            failures[0].error,
+            CommandError::Status(ReportStatus::NotSupported)
+        ));
+        assert_eq!(
+            failures[0].error.to_string(),
+            "Failed to send message: the mouse doesn't support this command."
+        );
+        assert_eq!(device.sent.borrow().len(), 1);
+    }
+
+    #[test]
+    fn apply_reports_mismatched_echo() {
+        let device = MockDevice::new();
+        device.push_response(&response(
+            &disable(MouseButton::Side2),
+            ReportStatus::Success,
+        ));
+
+        let failures = apply_functions(
+            &device,
+            Profile::default(),
+            &[disable(MouseButton::Side1)],
+            &RetryPolicy::no_wait(0),
+        )
+        .unwrap_err();
+        assert!(matches!(failures[0].error, CommandError::Mismatch { .. }));
+    }
+
+    #[test]
+    fn apply_gives_up_on_rejected_send() {
+        let device = MockDevice::rejecting();
+        let funcs = vec![disable(MouseButton::Side1), disable(MouseButton::Side2)];
+        let failures = apply_functions(
+            &device,
+            Profile::default(),
+            &funcs,
+            &RetryPolicy::no_wait(3),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            failures.as_slice(),
+            [FailedFunction {
+                index: 0,
+                error: CommandError::Send(_),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn apply_funcs_ron() {
+        let funcs = funcs_ron();
+        let naga = naga_with(Profile::default(), &funcs);
+
+        for func in &funcs {
+            assert_eq!(
+                naga.binding(func.button as u8),
+                Some(func.generate_parameter_string())
+            );
+        }
+    }
+
+    #[test]
+    fn read_back_bindings() {
+        let funcs = funcs_ron();
+        let naga = naga_with(Profile::default(), &funcs);
+
+        for func in &funcs {
+            assert_eq!(
+                get_binding(
+                    &naga,
+                    Profile::default(),
+                    func.key(),
+                    &RetryPolicy::no_wait(0)
+                )
+                .unwrap(),
+                *func
+            );
+        }
+        let all = all_bindings(&naga, Profile::default(), &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(all.len(), MouseButton::ALL.len());
+        assert!(all.contains(&disable(MouseButton::LClick)));
+    }
+
+    #[test]
+    fn only_changes_are_applied() {
+        let mut funcs = funcs_ron();
+        let naga = naga_with(Profile::default(), &funcs);
+        assert!(
+            changed_functions(&naga, Profile::default(), &funcs, &RetryPolicy::no_wait(0))
+                .unwrap()
+                .is_empty()
+        );
+
+        funcs[0].action = Action::Hypershift;
+        funcs.push(Function {
+            button: MouseButton::LScroll,
+            layer: Layer::Base,
+            action: Action::Hypershift,
+        });
+        assert_eq!(
+            changed_functions(&naga, Profile::default(), &funcs, &RetryPolicy::no_wait(0)).unwrap(),
+            vec![funcs[0].clone(), funcs[funcs.len() - 1].clone()]
+        );
+    }
+
+    #[test]
+    fn verify_catches_dropped_writes() {
+        let funcs = funcs_ron();
+        let naga = naga_with(Profile::default(), &funcs[..6]);
+        naga.set_drop_writes(true);
+        apply_functions(&naga, Profile::default(), &funcs, &RetryPolicy::no_wait(0)).unwrap();
+
+        let mismatches =
+            verify_functions(&naga, Profile::default(), &funcs, &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(mismatches.len(), funcs.len() - 6);
+        assert_eq!(
+            mismatches[0],
+            Mismatch {
+                wanted: funcs[6].clone(),
+                actual: disable(funcs[6].button),
+            }
+        );
+        assert!(verify_functions(
+            &naga,
+            Profile::default(),
+            &funcs[..6],
+            &RetryPolicy::no_wait(0)
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn asleep_rejects_everything() {
+        let naga = EmulatedNaga::new();
+        naga.set_asleep(true);
+        let funcs = vec![disable(MouseButton::Side1)];
+        let failures = apply_functions(&naga, Profile::default(), &funcs, &RetryPolicy::no_wait(2))
+            .unwrap_err();
+        assert!(matches!(
+            failures.as_slice(),
+            [FailedFunction {
+                index: 0,
+                error: CommandError::Status(ReportStatus::Timeout),
+                ..
+            }]
+        ));
+        assert_eq!(naga.binding(MouseButton::Side1 as u8), None);
+    }
+
+    #[test]
+    fn wait_for_wake_outlasts_retries() {
+        let naga = EmulatedNaga::new();
+        naga.set_asleep_for(10);
+        let funcs = vec![disable(MouseButton::Side1)];
+        assert!(
+            apply_functions(&naga, Profile::default(), &funcs, &RetryPolicy::no_wait(2)).is_err()
+        );
+        apply_functions(
+            &naga,
+            Profile::default(),
+            &funcs,
+            &RetryPolicy {
+                wait_for_wake: Some(Duration::from_secs(60)),
+                ..RetryPolicy::no_wait(2)
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            naga.binding(MouseButton::Side1 as u8),
+            Some(funcs[0].generate_parameter_string())
+        );
+    }
+
+    #[test]
+    fn busy_is_polled_through() {
+        let naga = EmulatedNaga::new();
+        naga.set_busy_for(5);
+        let funcs = vec![disable(MouseButton::Side1)];
+        apply_functions(&naga, Profile::default(), &funcs, &RetryPolicy::no_wait(0)).unwrap();
+    }
+
+    #[test]
+    fn failed_apply_is_rolled_back() {
+        let funcs = funcs_ron();
+        let naga = naga_with(Profile::default(), &funcs[..3]);
+        let before = all_bindings(&naga, Profile::default(), &RetryPolicy::no_wait(0)).unwrap();
+
+        let replacements: Vec<Function> = funcs
+            .iter()
+            .map(|func| Function {
+                button: func.button,
+                layer: Layer::Base,
+                action: Action::Hypershift,
+            })
+            .collect();
+        naga.set_fail_write(4);
+        let error = apply_transaction(
+            &naga,
+            Profile::default(),
+            &replacements,
+            &RetryPolicy::no_wait(0),
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            TransactionError::RolledBack {
+                cause: RollbackCause::Apply(ref failures),
+                rollback: Ok(()),
+            } if failures.len() == 1 && failures[0].index == 4
+        ));
+        assert_eq!(
+            all_bindings(&naga, Profile::default(), &RetryPolicy::no_wait(0)).unwrap(),
+            before
+        );
+    }
+
+    #[test]
+    fn failed_verify_is_rolled_back() {
+        let funcs = funcs_ron();
+        let naga = EmulatedNaga::new();
+        apply_transaction(
+            &naga,
+            Profile::default(),
+            &funcs[..3],
+            &RetryPolicy::no_wait(0),
+            true,
+        )
+        .unwrap();
+
+        naga.set_drop_writes(true);
+        let error = apply_transaction(
+            &naga,
+            Profile::default(),
+            &funcs,
+            &RetryPolicy::no_wait(0),
+            true,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            TransactionError::RolledBack {
+                cause: RollbackCause::Verify(ref mismatches),
+                rollback: Ok(()),
+            } if mismatches.len() == funcs.len() - 3
+        ));
+    }
+
+    #[test]
+    fn unreadable_mouse_is_left_alone() {
+        let naga = EmulatedNaga::new();
+        naga.set_asleep_for(1);
+        let funcs = vec![Function {
+            button: MouseButton::Side1,
+            layer: Layer::Base,
+            action: Action::Hypershift,
+        }];
+        assert!(matches!(
+            apply_transaction(
+                &naga,
+                Profile::default(),
+                &funcs,
+                &RetryPolicy::no_wait(0),
+                false
+            ),
+            Err(TransactionError::Snapshot(CommandError::Status(
+                ReportStatus::Timeout
+            )))
+        ));
+        assert_eq!(naga.binding(MouseButton::Side1 as u8), None);
+    }
+
+    #[test]
+    fn profiles_are_kept_apart() {
+        let funcs = funcs_ron();
+        let naga = naga_with(Profile::Slot(3), &funcs);
+        assert_eq!(naga.binding(funcs[0].button as u8), None);
+        assert_eq!(
+            naga.profile_binding(3, funcs[0].button as u8, Layer::Base as u8),
+            Some(funcs[0].generate_parameter_string())
+        );
+        assert!(
+            verify_functions(&naga, Profile::Slot(3), &funcs, &RetryPolicy::no_wait(0))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            verify_functions(&naga, Profile::Volatile, &funcs, &RetryPolicy::no_wait(0))
+                .unwrap()
+                .len(),
+            funcs.len()
+        );
+        assert!(matches!(
+            apply_functions(
+                &naga,
+                Profile::Slot(9),
+                &funcs[..1],
+                &RetryPolicy::no_wait(0)
+            )
+            .unwrap_err()
+            .as_slice(),
+            [FailedFunction {
+                error: CommandError::Status(ReportStatus::Failure),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn switch_active_profile() {
+        let naga = EmulatedNaga::new();
+        assert_eq!(
+            get_active_profile(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            Profile::default()
+        );
+        set_active_profile(&naga, Profile::Slot(4), &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(
+            get_active_profile(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            Profile::Slot(4)
+        );
+        assert!(matches!(
+            set_active_profile(&naga, Profile::Volatile, &RetryPolicy::no_wait(0)),
+            Err(CommandError::Status(ReportStatus::Failure))
+        ));
+
+        assert_eq!(
+            available_profiles(&naga, &RetryPolicy::no_wait(0))
+                .unwrap()
+                .len(),
+            Profile::SLOTS as usize
+        );
+        naga.set_slots(2);
+        assert_eq!(
+            available_profiles(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            vec![Profile::Slot(1), Profile::Slot(2)]
+        );
+    }
+
+    #[test]
+    fn layers_are_kept_apart() {
+        let key = Function {
+            button: MouseButton::Side2,
+            layer: Layer::Base,
+            action: Action::Hypershift,
+        };
+        let shifted: Vec<Function> = funcs_ron()
+            .into_iter()
+            .map(|func| Function {
+                layer: Layer::Hypershift,
+                ..func
+            })
+            .collect();
+        let naga = naga_with(Profile::default(), &shifted);
+        assert_eq!(naga.binding(MouseButton::Side1 as u8), None);
+        assert!(verify_functions(
+            &naga,
+            Profile::default(),
+            &shifted,
+            &RetryPolicy::no_wait(0)
+        )
+        .unwrap()
+        .is_empty());
+
+        // Side2 becomes the Hypershift key, which takes the place of what it did in the Hypershift layer.
+        apply_functions(
+            &naga,
+            Profile::default(),
+            std::slice::from_ref(&key),
+            &RetryPolicy::no_wait(0),
+        )
+        .unwrap();
+        let all = all_bindings(&naga, Profile::default(), &RetryPolicy::no_wait(0)).unwrap();
+        assert!(all.contains(&key));
+        assert_eq!(all.iter().filter(|f| f.button == key.button).count(), 1);
+        assert_eq!(all.len(), MouseButton::ALL.len() + shifted.len() - 1);
+    }
+
+    #[test]
+    fn dpi_stages() {
+        let naga = EmulatedNaga::new();
+        let stages = get_dpi_stages(&naga, Profile::default(), &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(stages.active, 2);
+        assert_eq!(stages.stages.len(), 5);
+
+        let stages = DpiStages {
+            active: 1,
+            stages: vec![Dpi { x: 100, y: 100 }, Dpi { x: 30000, y: 20000 }],
+        };
+        set_dpi_stages(&naga, Profile::Slot(2), &stages, &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(
+            get_dpi_stages(&naga, Profile::Slot(2), &RetryPolicy::no_wait(0)).unwrap(),
+            stages
+        );
+        assert_ne!(
+            get_dpi_stages(&naga, Profile::default(), &RetryPolicy::no_wait(0)).unwrap(),
+            stages
+        );
+
+        let too_fast = DpiStages {
+            active: 1,
+            stages: vec![Dpi { x: 30001, y: 800 }],
+        };
+        assert!(matches!(
+            set_dpi_stages(&naga, Profile::Slot(2), &too_fast, &RetryPolicy::no_wait(0)),
+            Err(CommandError::Status(ReportStatus::Failure))
+        ));
+    }
+
+    #[test]
+    fn current_dpi() {
+        let naga = EmulatedNaga::new();
+        assert_eq!(
+            get_dpi(&naga, Profile::Volatile, &RetryPolicy::no_wait(0)).unwrap(),
+            Dpi { x: 800, y: 800 }
+        );
+        let dpi = Dpi { x: 1600, y: 1200 };
+        set_dpi(&naga, Profile::Volatile, dpi, &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(
+            get_dpi(&naga, Profile::Volatile, &RetryPolicy::no_wait(0)).unwrap(),
+            dpi
+        );
+        assert!(matches!(
+            set_dpi(
+                &naga,
+                Profile::Volatile,
+                Dpi { x: 99, y: 800 },
+                &RetryPolicy::no_wait(0)
+            ),
+            Err(CommandError::Status(ReportStatus::Failure))
+        ));
+    }
+
+    #[test]
+    fn polling_rate_is_read_back() {
+        let naga = EmulatedNaga::new();
+        assert_eq!(
+            get_polling_rate(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            PollingRate::Hz1000
+        );
+        set_polling_rate(&naga, PollingRate::Hz125, &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(
+            get_polling_rate(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            PollingRate::Hz125
+        );
+
+        naga.set_drop_writes(true);
+        let error =
+            set_polling_rate(&naga, PollingRate::Hz500, &RetryPolicy::no_wait(0)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The mouse accepted 500 Hz but is still at 125 Hz"
+        );
+    }
+
+    #[test]
+    fn battery() {
+        let naga = EmulatedNaga::new();
+        naga.set_battery(191, true);
+        let battery = get_battery(&naga, &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(battery.percent, 75);
+        assert!(battery.charging);
+
+        naga.set_asleep(true);
+        assert!(get_battery(&naga, &RetryPolicy::no_wait(0)).is_err());
+    }
+
+    #[test]
+    fn power_settings() {
+        let naga = EmulatedNaga::new();
+        assert_eq!(
+            get_idle_seconds(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            300
+        );
+        assert_eq!(
+            get_low_battery_percent(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            15
+        );
+
+        set_idle_seconds(&naga, 900, &RetryPolicy::no_wait(0)).unwrap();
+        set_low_battery_percent(&naga, 20, &RetryPolicy::no_wait(0)).unwrap();
+        assert_eq!(
+            get_idle_seconds(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            900
+        );
+        assert_eq!(
+            get_low_battery_percent(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            20
+        );
+        assert!(matches!(
+            set_idle_seconds(&naga, 30, &RetryPolicy::no_wait(0)),
+            Err(CommandError::Status(ReportStatus::Failure))
+        ));
+    }
+
+    #[test]
+    fn device_info() {
+        let naga = EmulatedNaga::new();
+        assert_eq!(
+            get_firmware_version(&naga, &RetryPolicy::no_wait(0))
+                .unwrap()
+                .to_string(),
+            "v1.02"
+        );
+        assert_eq!(get_serial(&naga, &RetryPolicy::no_wait(0)).unwrap(), SERIAL);
+        assert_eq!(
+            get_device_mode(&naga, &RetryPolicy::no_wait(0)).unwrap(),
+            DeviceMode::Normal
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use crate::model::{Action, Function, Layer, MouseButton};
+use std::fmt;
+
+/// What applying a config would do to one button.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum BindingChange<'a> {
+    /// The button is currently disabled and would get something assigned.
+    Added(&'a Function),
+    Changed {
+        current: &'a Function,
+        wanted: &'a Function,
+    },
+    Unchanged(&'a Function),
+}
+
+impl fmt::Display for BindingChange<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingChange::Added(wanted) => write!(f, "+ {}", wanted),
+            BindingChange::Changed { current, wanted } => {
+                write!(f, "~ {} (currently {})", wanted, current.action)
+            }
+            BindingChange::Unchanged(wanted) => write!(f, "  {}", wanted),
+        }
+    }
+}
+
+/// The buttons a list of functions touches and on which layer, each only once.
+pub(crate) fn keys(functions: &[Function]) -> Vec<(MouseButton, Layer)> {
+    let mut keys = vec![];
+    for func in functions {
+        if !keys.contains(&func.key()) {
+            keys.push(func.key());
+        }
+    }
+    keys
+}
+
+/// Compares what's on the mouse against what a config wants. If the config assigns the same button and layer more than once,
+/// only the last one counts since that's what the button ends up with. Buttons the config doesn't mention aren't
+/// included, and neither are config buttons missing from `current`.
+pub(crate) fn diff<'a>(current: &'a [Function], wanted: &'a [Function]) -> Vec<BindingChange<'a>> {
+    wanted
+        .iter()
+        .enumerate()
+        .filter(|(i, func)| !wanted[i + 1..].iter().any(|f| f.key() == func.key()))
+        .filter_map(|(_, wanted)| {
+            let current = current.iter().find(|f| f.key() == wanted.key())?;
+            Some(if current == wanted {
+                BindingChange::Unchanged(wanted)
+            } else if matches!(current.action, Action::Disable) {
+                BindingChange::Added(wanted)
+            } else {
+                BindingChange::Changed { current, wanted }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff, keys, BindingChange};
+    use crate::model::{disable, Action, Function, Layer, MouseButton, SensitivityFunction};
+
+    fn hypershift(button: MouseButton) -> Function {
+        Function {
+            button,
+            layer: Layer::Base,
+            action: Action::Hypershift,
+        }
+    }
+
+    fn stage_up(button: MouseButton) -> Function {
+        Function {
+            button,
+            layer: Layer::Base,
+            action: Action::Sensitivity(SensitivityFunction::StageUp),
+        }
+    }
+
+    #[test]
+    fn classify_changes() {
+        let current = vec![
+            disable(MouseButton::Side1),
+            disable(MouseButton::Side2),
+            stage_up(MouseButton::Side3),
+        ];
+        let wanted = vec![
+            stage_up(MouseButton::Side1),
+            disable(MouseButton::Side2),
+            disable(MouseButton::Side3),
+        ];
+        assert_eq!(
+            diff(&current, &wanted),
+            vec![
+                BindingChange::Added(&wanted[0]),
+                BindingChange::Unchanged(&wanted[1]),
+                BindingChange::Changed {
+                    current: &current[2],
+                    wanted: &wanted[2],
+                },
+            ]
+        );
+        assert_eq!(
+            BindingChange::Changed {
+                current: &current[2],
+                wanted: &wanted[2],
+            }
+            .to_string(),
+            "~ Side3: disabled (currently sensitivity StageUp)"
+        );
+    }
+
+    #[test]
+    fn last_assignment_wins() {
+        let current = vec![disable(MouseButton::Side1)];
+        let wanted = vec![stage_up(MouseButton::Side1), disable(MouseButton::Side1)];
+        assert_eq!(
+            diff(&current, &wanted),
+            vec![BindingChange::Unchanged(&wanted[1])]
+        );
+        assert_eq!(keys(&wanted), vec![(MouseButton::Side1, Layer::Base)]);
+    }
+
+    #[test]
+    fn layers_are_separate() {
+        let mut shifted = disable(MouseButton::Side1);
+        shifted.layer = Layer::Hypershift;
+        let current = vec![disable(MouseButton::Side1), shifted];
+        let wanted = vec![disable(MouseButton::Side1), hypershift(MouseButton::Side1)];
+        // The Hypershift key itself goes in the Hypershift layer.
+        assert_eq!(
+            keys(&wanted),
+            vec![
+                (MouseButton::Side1, Layer::Base),
+                (MouseButton::Side1, Layer::Hypershift)
+            ]
+        );
+        assert_eq!(
+            diff(&current, &wanted),
+            vec![
+                BindingChange::Unchanged(&wanted[0]),
+                BindingChange::Added(&wanted[1])
+            ]
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use crate::model::{Layer, Profile};
+use crate::report::{RazerReport, ReportStatus, REPORT_LEN};
+use crate::transport::FeatureReportDevice;
+use hidapi::{HidError, HidResult};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+/// A software stand-in for the naga v2 hyperspeed. It understands the same report layout `RazerReport` describes,
+/// keeps track of what each button is bound to, and answers with a status byte the way the real mouse does.
+#[derive(Default)]
+pub(crate) struct EmulatedNaga {
+    /// Keyed by profile byte, button, then layer.
+    bindings: RefCell<HashMap<(u8, u8, u8), [u8; 9]>>,
+    pending: RefCell<Option<[u8; REPORT_LEN]>>,
+    asleep: Cell<bool>,
+    asleep_for: Cell<u32>,
+    busy_for: Cell<u32>,
+    drop_writes: Cell<bool>,
+    fail_write: Cell<Option<u32>>,
+    /// Profile byte of the slot in use, 0 standing in for the default.
+    active_profile: Cell<u8>,
+    /// How many onboard slots there are, 0 standing in for all of them.
+    slots: Cell<u8>,
+    /// Raw DPI stage tables by profile byte, from the active stage onwards.
+    dpi_stages: RefCell<HashMap<u8, Vec<u8>>>,
+    /// Raw X and Y of the current DPI by profile byte.
+    dpi: RefCell<HashMap<u8, [u8; 4]>>,
+    /// Raw polling rate byte, 0 standing in for the default of 1000 Hz.
+    polling_rate: Cell<u8>,
+    battery_level: Cell<u8>,
+    charging: Cell<bool>,
+    /// Raw idle time and low battery threshold, 0 standing in for the defaults.
+    idle_seconds: Cell<u16>,
+    low_battery: Cell<u8>,
+}
+
+/// Made up, but in the same format as the serial on the bottom of the mouse.
+pub(crate) const SERIAL: &str = "PM2213H00000000";
+
+/// What the stage buttons cycle through out of the box.
+const DEFAULT_DPI_STAGES: [u16; 5] = [400, 800, 1600, 3200, 6400];
+
+impl EmulatedNaga {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// While asleep every report is answered with status 4, same as the real mouse does when it's dozed off.
+    pub(crate) fn set_asleep(&self, asleep: bool) {
+        self.asleep.set(asleep);
+    }
+
+    /// Sleep through the next few reports, then wake up.
+    pub(crate) fn set_asleep_for(&self, reports: u32) {
+        self.asleep_for.set(reports);
+    }
+
+    /// Answer the next few reads with busy before giving the real response.
+    pub(crate) fn set_busy_for(&self, reads: u32) {
+        self.busy_for.set(reads);
+    }
+
+    /// Acknowledge assignments and the polling rate as successful without actually storing them.
+    pub(crate) fn set_drop_writes(&self, drop_writes: bool) {
+        self.drop_writes.set(drop_writes);
+    }
+
+    /// Let this many more assignments through, then fail the one after with status 3. Only happens once.
+    pub(crate) fn set_fail_write(&self, after: u32) {
+        self.fail_write.set(Some(after));
+    }
+
+    /// Battery level as the mouse reports it, 0 to 255.
+    pub(crate) fn set_battery(&self, level: u8, charging: bool) {
+        self.battery_level.set(level);
+        self.charging.set(charging);
+    }
+
+    /// Pretend to be a mouse with fewer onboard slots.
+    pub(crate) fn set_slots(&self, slots: u8) {
+        self.slots.set(slots);
+    }
+
+    fn has_profile(&self, profile: u8) -> bool {
+        match self.slots.get() {
+            0 => Profile::try_from(profile).is_ok(),
+            slots => profile <= slots,
+        }
+    }
+
+    /// The raw parameter string (bytes 10-18 of the report) currently stored for a button in the default profile, if
+    /// anything was ever sent.
+    pub(crate) fn binding(&self, button: u8) -> Option<[u8; 9]> {
+        self.profile_binding(Profile::default().byte(), button, Layer::Base as u8)
+    }
+
+    /// Same as `binding`, but for any profile and layer.
+    pub(crate) fn profile_binding(&self, profile: u8, button: u8, layer: u8) -> Option<[u8; 9]> {
+        self.bindings
+            .borrow()
+            .get(&(profile, button, layer))
+            .copied()
+    }
+
+    fn process(&self, request: &[u8]) -> Result<RazerReport, ReportStatus> {
+        if self.asleep.get() {
+            return Err(ReportStatus::Timeout);
+        }
+        if self.asleep_for.get() > 0 {
+            self.asleep_for.set(self.asleep_for.get() - 1);
+            return Err(ReportStatus::Timeout);
+        }
+        let Ok(mut report) = RazerReport::parse(request) else {
+            return Err(ReportStatus::Failure);
+        };
+        match (report.command_class, report.command_id) {
+            (0x02, _) | (0x05, 0x02) if !self.has_profile(report.arguments[0]) => {
+                return Err(ReportStatus::Failure)
+            }
+            (0x05, 0x02) if report.arguments[0] == Profile::Volatile.byte() => {
+                return Err(ReportStatus::Failure)
+            }
+            (0x05, 0x02) => self.active_profile.set(report.arguments[0]),
+            (0x05, 0x82) => {
+                report.arguments[0] = match self.active_profile.get() {
+                    0 => Profile::default().byte(),
+                    active => active,
+                };
+            }
+            (0x00, 0x81) => report.arguments[..2].clone_from_slice(&[1, 2]),
+            (0x00, 0x82) => report.arguments[..SERIAL.len()].clone_from_slice(SERIAL.as_bytes()),
+            (0x00, 0x84) => report.arguments[..2].clone_from_slice(&[0, 0]),
+            (0x07, 0x03) => {
+                let seconds = u16::from_be_bytes([report.arguments[0], report.arguments[1]]);
+                if !(60..=900).contains(&seconds) {
+                    return Err(ReportStatus::Failure);
+                }
+                self.idle_seconds.set(seconds);
+            }
+            (0x07, 0x83) => {
+                let seconds = match self.idle_seconds.get() {
+                    0 => 300,
+                    seconds => seconds,
+                };
+                report.arguments[..2].clone_from_slice(&seconds.to_be_bytes());
+            }
+            (0x07, 0x01) => self.low_battery.set(report.arguments[0]),
+            (0x07, 0x81) => {
+                report.arguments[0] = match self.low_battery.get() {
+                    0 => 0x26,
+                    raw => raw,
+                };
+            }
+            (0x07, 0x80) => report.arguments[1] = self.battery_level.get(),
+            (0x07, 0x84) => report.arguments[1] = self.charging.get() as u8,
+            (0x00, 0x05) => {
+                if ![0x01, 0x02, 0x04, 0x08].contains(&report.arguments[0]) {
+                    return Err(ReportStatus::Failure);
+                }
+                if !self.drop_writes.get() {
+                    self.polling_rate.set(report.arguments[0]);
+                }
+            }
+            (0x00, 0x85) => {
+                report.arguments[0] = match self.polling_rate.get() {
+                    0 => 0x01,
+                    rate => rate,
+                };
+            }
+            (0x04, 0x05) => {
+                let mut dpi = [0u8; 4];
+                dpi.clone_from_slice(&report.arguments[1..5]);
+                let in_range = [[dpi[0], dpi[1]], [dpi[2], dpi[3]]]
+                    .iter()
+                    .all(|dpi| (100..=30000).contains(&u16::from_be_bytes(*dpi)));
+                if !in_range {
+                    return Err(ReportStatus::Failure);
+                }
+                self.dpi.borrow_mut().insert(report.arguments[0], dpi);
+            }
+            (0x04, 0x85) => {
+                let stored = self.dpi.borrow().get(&report.arguments[0]).copied();
+                let [x_hi, x_lo] = DEFAULT_DPI_STAGES[1].to_be_bytes();
+                report.arguments[1..5]
+                    .clone_from_slice(&stored.unwrap_or([x_hi, x_lo, x_hi, x_lo]));
+            }
+            (0x04, 0x06) => {
+                let (active, count) = (report.arguments[1], report.arguments[2]);
+                if !(1..=5).contains(&count) || !(1..=count).contains(&active) {
+                    return Err(ReportStatus::Failure);
+                }
+                let table = &report.arguments[3..3 + 7 * count as usize];
+                let in_range = table.chunks(7).all(|stage| {
+                    [[stage[1], stage[2]], [stage[3], stage[4]]]
+                        .iter()
+                        .all(|dpi| (100..=30000).contains(&u16::from_be_bytes(*dpi)))
+                });
+                if !in_range {
+                    return Err(ReportStatus::Failure);
+                }
+                self.dpi_stages
+                    .borrow_mut()
+                    .insert(report.arguments[0], report.args()[1..].to_vec());
+            }
+            (0x04, 0x86) => {
+                let stored = self.dpi_stages.borrow().get(&report.arguments[0]).cloned();
+                let table = stored.unwrap_or_else(|| {
+                    let mut table = vec![2, DEFAULT_DPI_STAGES.len() as u8];
+                    for (i, dpi) in DEFAULT_DPI_STAGES.iter().enumerate() {
+                        table.push(i as u8 + 1);
+                        table.extend(dpi.to_be_bytes());
+                        table.extend(dpi.to_be_bytes());
+                        table.extend([0, 0]);
+                    }
+                    table
+                });
+                report.arguments[1..1 + table.len()].clone_from_slice(&table);
+            }
+            (0x02, 0x0c) => {
+                match self.fail_write.get() {
+                    Some(0) => {
+                        self.fail_write.set(None);
+                        return Err(ReportStatus::Failure);
+                    }
+                    Some(n) => self.fail_write.set(Some(n - 1)),
+                    None => {}
+                }
+                let mut params = [0u8; 9];
+                params.clone_from_slice(&report.arguments[1..10]);
+                if !self.drop_writes.get() {
+                    self.bindings
+                        .borrow_mut()
+                        .insert((report.arguments[0], params[0], params[1]), params);
+                }
+            }
+            (0x02, 0x8c) => {
+                // Anything that was never assigned reads back as disabled.
+                let (button, layer) = (report.arguments[1], report.arguments[2]);
+                let params = self
+                    .profile_binding(report.arguments[0], button, layer)
+                    .unwrap_or([button, layer, 0, 0, 0, 0, 0, 0, 0]);
+                report.arguments[1..10].clone_from_slice(&params);
+            }
+            _ => return Err(ReportStatus::NotSupported),
+        }
+        report.status = ReportStatus::Success;
+        report.checksum = report.calculate_checksum();
+        Ok(report)
+    }
+}
+
+impl FeatureReportDevice for EmulatedNaga {
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()> {
+        let mut response: [u8; REPORT_LEN] =
+            data.try_into().map_err(|_| HidError::HidApiError {
+                message: format!(
+                    "expected a {} byte report, got {} bytes",
+                    REPORT_LEN,
+                    data.len()
+                ),
+            })?;
+        // Anything the emulator can't handle gets the request echoed back with the failing status, like the mouse does.
+        match self.process(data) {
+            Ok(report) => response = report.to_bytes(),
+            Err(status) => response[1] = status as u8,
+        }
+        *self.pending.borrow_mut() = Some(response);
+        Ok(())
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        let response = if self.busy_for.get() > 0 {
+            self.busy_for.set(self.busy_for.get() - 1);
+            self.pending.borrow().map(|mut busy| {
+                busy[1] = ReportStatus::Busy as u8;
+                busy
+            })
+        } else {
+            self.pending.borrow_mut().take()
+        }
+        .ok_or_else(|| HidError::HidApiError {
+            message: "no report was sent to respond to".into(),
+        })?;
+        let len = response.len().min(buf.len());
+        buf[..len].copy_from_slice(&response[..len]);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EmulatedNaga;
+    use crate::device::{transact, CommandError, RetryPolicy};
+    use crate::model::{disable, generate_message, MouseButton};
+    use crate::report::{RazerReport, ReportStatus, REPORT_LEN};
+    use crate::transport::FeatureReportDevice;
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let naga = EmulatedNaga::new();
+        let mut message = generate_message(&disable(MouseButton::Side1));
+        message[89] ^= 0xff;
+        naga.send_feature_report(&message).unwrap();
+
+        let mut buf = [0u8; REPORT_LEN];
+        naga.get_feature_report(&mut buf).unwrap();
+        assert_eq!(buf[1], ReportStatus::Failure as u8);
+        assert_eq!(naga.binding(MouseButton::Side1 as u8), None);
+    }
+
+    #[test]
+    fn oversized_stage_table_fails() {
+        let naga = EmulatedNaga::new();
+        // More stages than there's room for in a report.
+        assert!(matches!(
+            transact(
+                &naga,
+                &RazerReport::new(0x04, 0x06, &[1, 1, 12]),
+                &RetryPolicy::no_wait(0)
+            ),
+            Err(CommandError::Status(ReportStatus::Failure))
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::report::REPORT_LEN;
+use hidapi::{DeviceInfo, HidApi, HidError};
+use std::fmt;
+
+/// Generic desktop / mouse. The naga only takes its feature reports on the interface that describes itself as the
+/// mouse, the keyboard and consumer control ones just refuse them.
+const GENERIC_DESKTOP: u16 = 0x01;
+const MOUSE: u16 = 0x02;
+
+/// Report descriptors are capped at 4096 bytes by the HID spec.
+const MAX_DESCRIPTOR_LEN: usize = 4096;
+
+/// Why no mouse was picked to talk to.
+#[derive(Debug)]
+pub(crate) enum SelectError {
+    NotFound,
+    /// There is a naga, but none of its interfaces has the feature report the commands go through.
+    NoControlInterface,
+    /// An interface that could be the control one couldn't be opened to check, typically for lack of permissions.
+    Open {
+        path: String,
+        error: HidError,
+    },
+    /// More than one naga to choose from, described so the user can tell which to pick.
+    Ambiguous(Vec<String>),
+}
+
+impl fmt::Display for SelectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectError::NotFound => write!(f, "No naga v2 hyperspeed found."),
+            SelectError::NoControlInterface => write!(
+                f,
+                "Found a naga v2 hyperspeed, but none of its interfaces takes control reports."
+            ),
+            SelectError::Open { path, error } => write!(f, "Unable to open {}: {}", path, error),
+            SelectError::Ambiguous(candidates) => {
+                write!(
+                    f,
+                    "Found {} naga v2 hyperspeeds, pick one with --serial or --device:",
+                    candidates.len()
+                )?;
+                for candidate in candidates {
+                    write!(f, "\n  {}", candidate)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What `--device` and `--serial` narrow the nagas down to.
+#[derive(Debug, Default)]
+pub(crate) struct Selection {
+    pub(crate) path: Option<String>,
+    pub(crate) serial: Option<String>,
+}
+
+impl Selection {
+    pub(crate) fn wants_path(&self, path: &str) -> bool {
+        self.path.as_deref().is_none_or(|wanted| wanted == path)
+    }
+
+    /// `serial` is only called if a serial number was asked for, since finding it out can mean asking the mouse.
+    pub(crate) fn wants_serial(&self, serial: impl FnOnce() -> Option<String>) -> bool {
+        self.serial
+            .as_ref()
+            .is_none_or(|wanted| serial().as_ref() == Some(wanted))
+    }
+}
+
+/// The only one of `candidates`. Anything that changes the mouse has to know which one it's changing, so more than one
+/// is an error rather than picking whichever came first.
+pub(crate) fn pick_one<T>(
+    mut candidates: Vec<T>,
+    describe: impl Fn(&T) -> String,
+) -> Result<T, SelectError> {
+    match candidates.len() {
+        0 => Err(SelectError::NotFound),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(SelectError::Ambiguous(
+            candidates.iter().map(describe).collect(),
+        )),
+    }
+}
+
+/// Whether `device` is the interface that commands are sent to. Goes by usage first, then the report descriptor if
+/// it can be read. Nothing gets sent to the device to find out.
+pub(crate) fn is_control_interface(api: &HidApi, device: &DeviceInfo) -> Result<bool, SelectError> {
+    if device.usage_page() != GENERIC_DESKTOP || device.usage() != MOUSE {
+        return Ok(false);
+    }
+    let hid = api
+        .open_path(device.path())
+        .map_err(|error| SelectError::Open {
+            path: device.path().to_string_lossy().into_owned(),
+            error,
+        })?;
+    let mut descriptor = [0; MAX_DESCRIPTOR_LEN];
+    match hid.get_report_descriptor(&mut descriptor) {
+        Ok(len) => Ok(has_control_report(&descriptor[..len])),
+        // Not every platform hands out descriptors, so the usage will have to do.
+        Err(_) => Ok(true),
+    }
+}
+
+/// Whether the report descriptor declares a feature report the size of a razer report, without a report ID.
+pub(crate) fn has_control_report(descriptor: &[u8]) -> bool {
+    let mut globals = Globals::default();
+    let mut stack = Vec::new();
+    let mut rest = descriptor;
+    while let Some((&prefix, data)) = rest.split_first() {
+        // Long items, none of which are defined by the spec. Skip over them.
+        if prefix == 0xfe {
+            let Some(&len) = data.first() else {
+                return false;
+            };
+            rest = data.get(2 + len as usize..).unwrap_or_default();
+            continue;
+        }
+        let len = match prefix & 0b11 {
+            3 => 4,
+            len => len as usize,
+        };
+        let Some(value) = data.get(..len) else {
+            return false;
+        };
+        let value = value
+            .iter()
+            .rev()
+            .fold(0u32, |acc, byte| (acc << 8) | *byte as u32);
+        rest = &data[len..];
+
+        match prefix & 0b1111_1100 {
+            // Feature
+            0xb0 if globals.report_id == 0
+                && globals.report_size.checked_mul(globals.report_count)
+                    == Some((REPORT_LEN as u32 - 1) * 8) =>
+            {
+                return true;
+            }
+            0x74 => globals.report_size = value,
+            0x94 => globals.report_count = value,
+            0x84 => globals.report_id = value,
+            // Push and pop
+            0xa4 => stack.push(globals),
+            0xb4 => globals = stack.pop().unwrap_or_default(),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// The global items that matter for working out a report's size.
+#[derive(Clone, Copy, Default)]
+struct Globals {
+    report_size: u32,
+    report_count: u32,
+    report_id: u32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{has_control_report, pick_one, SelectError, Selection};
+    use hex_literal::hex;
+
+    /// Interface 0 of the naga, trimmed down to the mouse collection and the vendor feature report.
+    const MOUSE_DESCRIPTOR: [u8; 25] = hex!(
+        "05 01 09 02 a1 01"
+        "09 00 15 00 26 ff 00 75 08 95 5a b1 01"
+        "c0 05 01 09 00 c0"
+    );
+
+    #[test]
+    fn control_report() {
+        assert!(has_control_report(&MOUSE_DESCRIPTOR));
+        // A keyboard interface, only input and output reports.
+        assert!(!has_control_report(&hex!(
+            "05 01 09 06 a1 01 75 01 95 08 81 02 95 05 91 02 c0"
+        )));
+        // Right size, but behind a report ID.
+        assert!(!has_control_report(&hex!(
+            "05 01 09 02 a1 01 85 04 75 08 95 5a b1 01 c0"
+        )));
+        // A report ID set between push and pop doesn't stick.
+        assert!(has_control_report(&hex!("75 08 95 5a a4 85 04 b4 b1 01")));
+        // Truncated descriptors aren't read past the end.
+        assert!(!has_control_report(&MOUSE_DESCRIPTOR[..14]));
+    }
+
+    #[test]
+    fn selection() {
+        let any = Selection::default();
+        assert!(any.wants_path("/dev/hidraw3"));
+        assert!(any.wants_serial(|| panic!("shouldn't ask for the serial without --serial")));
+
+        let by_path = Selection {
+            path: Some("/dev/hidraw3".into()),
+            ..Selection::default()
+        };
+        assert!(by_path.wants_path("/dev/hidraw3"));
+        assert!(!by_path.wants_path("/dev/hidraw4"));
+
+        let by_serial = Selection {
+            serial: Some("PM2213H00000001".into()),
+            ..Selection::default()
+        };
+        assert!(by_serial.wants_serial(|| Some("PM2213H00000001".into())));
+        assert!(!by_serial.wants_serial(|| Some("PM2213H00000002".into())));
+        assert!(!by_serial.wants_serial(|| None));
+    }
+
+    #[test]
+    fn pick_only_one() {
+        let describe = |path: &&str| path.to_string();
+        assert!(matches!(
+            pick_one(vec!["/dev/hidraw3"], describe),
+            Ok("/dev/hidraw3")
+        ));
+        assert!(matches!(
+            pick_one(Vec::<&str>::new(), describe),
+            Err(SelectError::NotFound)
+        ));
+        assert!(matches!(
+            pick_one(vec!["/dev/hidraw3", "/dev/hidraw7"], describe),
+            Err(SelectError::Ambiguous(candidates)) if candidates == ["/dev/hidraw3", "/dev/hidraw7"]
+        ));
+    }
+}
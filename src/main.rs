@@ -1,83 +1,1144 @@
+mod config;
+mod connection;
+mod device;
+mod diff;
+#[cfg(test)]
+mod emulator;
+mod interface;
 mod model;
+mod report;
+mod settings;
+mod transport;
 
-use crate::model::Function;
-use hidapi::HidApi;
-use model::generate_message;
-use ron::extensions::Extensions;
-use ron::ser::PrettyConfig;
+use crate::config::Config;
+use crate::connection::{Connection, RAZER_VENDOR_ID};
+use crate::device::{
+    all_bindings, apply_functions, apply_transaction, available_profiles, changed_functions,
+    get_active_profile, get_battery, get_bindings, get_device_mode, get_dpi, get_dpi_stages,
+    get_firmware_version, get_idle_seconds, get_low_battery_percent, get_polling_rate, get_serial,
+    set_active_profile, set_dpi, set_dpi_stages, set_idle_seconds, set_low_battery_percent,
+    set_polling_rate, verify_functions, CommandError, RetryPolicy, RollbackCause, TransactionError,
+};
+use crate::diff::BindingChange;
+use crate::interface::{is_control_interface, pick_one, SelectError, Selection};
+use crate::model::{Function, Layer, MouseButton, Profile};
+use crate::settings::{validate_idle_seconds, validate_low_battery_percent, Dpi, PollingRate};
+use crate::transport::FeatureReportDevice;
+use clap::{Parser, Subcommand};
+use hidapi::{DeviceInfo, HidApi, HidDevice, HidResult};
 use std::{
-    fs::File,
-    io::{BufReader, BufWriter, Write, Read},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
 };
 
-fn main() {
-    println!("Searching for naga v2 hyperspeed...");
-
-    match HidApi::new() {
-        Ok(api) => {
-            'searchloop: for device in api.device_list() {
-                let vid = device.vendor_id();
-                let pid = device.product_id();
-                if vid == 0x1532 && pid == 0x00b4 {
-                    if device.interface_number() != 0 {
-                        continue;
+/// Assigns the buttons of a Razer Naga V2 HyperSpeed and changes its settings
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// How many times to resend a report the mouse didn't accept
+    #[arg(long, global = true, default_value_t = 3)]
+    retries: u32,
+    /// Delay before the first resend in milliseconds, doubled for every resend after that
+    #[arg(long, global = true, default_value_t = 50)]
+    backoff_ms: u64,
+    /// Keep resending to a sleeping mouse for up to this many seconds, giving you time to wake it up
+    #[arg(long, global = true, value_name = "SECONDS")]
+    wait_for_wake: Option<u64>,
+    /// Onboard profile to use, 1 to 5 or "volatile" to not save anything. Overrides the one in the config.
+    #[arg(long, global = true, value_parser = parse_profile)]
+    profile: Option<Profile>,
+    /// Config file to read, RON or JSON
+    #[arg(long, global = true, default_value = "funcs.ron")]
+    config: PathBuf,
+    /// Only talk to the device at this HID path, as shown by `list`
+    #[arg(long, global = true, value_name = "PATH")]
+    device: Option<String>,
+    /// Only talk to the mouse with this serial number, as shown by `list`
+    #[arg(long, global = true)]
+    serial: Option<String>,
+    /// Also look for the naga under this product ID, in hex. Only the dongle's is known, so this is how to reach it
+    /// over a cable or bluetooth.
+    #[arg(long, global = true, value_parser = parse_product_id)]
+    product_id: Option<u16>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(flatten)]
+    Mouse(MouseCommand),
+    /// Check the config for mistakes without touching the mouse
+    Validate,
+    /// Rewrite a config file as RON or JSON, going by the output's extension
+    Convert { input: PathBuf, output: PathBuf },
+}
+
+// Everything that needs a mouse to talk to. Not a doc comment, clap would take it for the program's description.
+#[derive(Subcommand)]
+enum MouseCommand {
+    /// Assign the functions in the config to their buttons and apply its settings. This is the default.
+    Apply {
+        /// Read the buttons first and only send the ones that aren't already assigned that way
+        #[arg(long)]
+        changed_only: bool,
+        /// Read every button back afterwards and fail if any of them didn't take
+        #[arg(long)]
+        verify: bool,
+        /// Read the buttons first and put them back the way they were if anything fails
+        #[arg(long)]
+        transactional: bool,
+        /// Show what would be sent without sending any of it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show what buttons are currently assigned to
+    Get {
+        /// Only show this button, e.g. Side1. Shows what it does on both layers.
+        #[arg(value_parser = parse_button)]
+        button: Option<MouseButton>,
+    },
+    /// Show what applying the config would change
+    Diff,
+    /// Read every button's current assignment off the mouse, in the same format the config is read in
+    Dump {
+        /// Where to write it. Files ending in .json get JSON, anything else RON. Prints RON without a path.
+        output: Option<PathBuf>,
+    },
+    /// Show the current sensitivity, or change it right away
+    Dpi {
+        /// New sensitivity, either one value for both axes or XxY, e.g. 1600 or 1600x800
+        #[arg(value_parser = parse_dpi)]
+        set: Option<Dpi>,
+    },
+    /// List every naga that was detected, with what `--device` and `--serial` need to pick it
+    List,
+    /// Show firmware version, serial number and where to find every naga that was detected
+    Info,
+    /// Show the battery level and whether it's charging
+    Battery {
+        /// Print it as JSON instead, e.g. for status bars
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show how long the mouse waits before sleeping and when it goes into low power mode, or change them
+    Power {
+        /// Seconds of inactivity before the mouse goes to sleep, 60 to 900
+        #[arg(long, value_name = "SECONDS", value_parser = parse_idle_seconds)]
+        idle: Option<u16>,
+        /// Battery percentage the mouse goes into low power mode at, 5 to 100
+        #[arg(long, value_name = "PERCENT", value_parser = parse_low_battery_percent)]
+        low_battery: Option<u8>,
+    },
+    /// Show the polling rate, or change it
+    PollingRate {
+        /// New polling rate in Hz: 125, 250, 500 or 1000
+        #[arg(value_parser = parse_polling_rate)]
+        set: Option<PollingRate>,
+    },
+    /// Show the DPI stages the stage buttons step through, with the active one marked
+    DpiStages,
+    /// Show which onboard profile the mouse is using, or switch to another one
+    Profile {
+        /// Slot to switch to, 1 to 5
+        #[arg(value_parser = parse_slot)]
+        switch_to: Option<Profile>,
+        /// List the slots the mouse has instead
+        #[arg(long, conflicts_with = "switch_to")]
+        list: bool,
+    },
+}
+
+/// Buttons are named the same as in funcs.ron.
+fn parse_button(s: &str) -> Result<MouseButton, String> {
+    ron::from_str(s).map_err(|_| format!("unknown button: {}", s))
+}
+
+fn parse_profile(s: &str) -> Result<Profile, String> {
+    if s.eq_ignore_ascii_case("volatile") {
+        return Ok(Profile::Volatile);
+    }
+    s.parse::<u8>()
+        .ok()
+        .filter(|n| *n != 0)
+        .and_then(|n| Profile::try_from(n).ok())
+        .ok_or_else(|| format!("expected 1 to {} or volatile", Profile::SLOTS))
+}
+
+fn parse_dpi(s: &str) -> Result<Dpi, String> {
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u16>()
+            .map_err(|_| format!("not a DPI value: {}", value))
+    };
+    let dpi = match s.split_once(['x', 'X']) {
+        Some((x, y)) => Dpi {
+            x: parse(x)?,
+            y: parse(y)?,
+        },
+        None => {
+            let value = parse(s)?;
+            Dpi { x: value, y: value }
+        }
+    };
+    dpi.validate().map_err(|e| e.to_string())?;
+    Ok(dpi)
+}
+
+fn parse_idle_seconds(s: &str) -> Result<u16, String> {
+    let seconds = s
+        .parse::<u16>()
+        .map_err(|_| format!("not a number of seconds: {}", s))?;
+    validate_idle_seconds(seconds).map_err(|e| e.to_string())?;
+    Ok(seconds)
+}
+
+fn parse_low_battery_percent(s: &str) -> Result<u8, String> {
+    let percent = s
+        .trim_end_matches('%')
+        .parse::<u8>()
+        .map_err(|_| format!("not a percentage: {}", s))?;
+    validate_low_battery_percent(percent).map_err(|e| e.to_string())?;
+    Ok(percent)
+}
+
+fn parse_polling_rate(s: &str) -> Result<PollingRate, String> {
+    let hz = s
+        .trim_end_matches("Hz")
+        .trim()
+        .parse::<u16>()
+        .map_err(|_| format!("not a polling rate: {}", s))?;
+    PollingRate::try_from(hz).map_err(|e| e.to_string())
+}
+
+/// Same as `parse_profile`, minus volatile since that's not something the mouse can be switched to.
+fn parse_slot(s: &str) -> Result<Profile, String> {
+    match parse_profile(s)? {
+        Profile::Volatile => Err("volatile isn't a slot the mouse can switch to".into()),
+        slot => Ok(slot),
+    }
+}
+
+/// A product ID in hex, with or without the 0x.
+fn parse_product_id(s: &str) -> Result<u16, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|e| format!("{} isn't a hex product ID: {}", s, e))
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let policy = RetryPolicy {
+        retries: args.retries,
+        backoff: Duration::from_millis(args.backoff_ms),
+        wait_for_wake: args.wait_for_wake.map(Duration::from_secs),
+        ..RetryPolicy::default()
+    };
+
+    let command = match args.command {
+        Some(Command::Mouse(command)) => command,
+        Some(Command::Validate) => return validate(&args.config),
+        Some(Command::Convert { input, output }) => return convert(&input, &output),
+        None => MouseCommand::Apply {
+            changed_only: false,
+            verify: false,
+            transactional: false,
+            dry_run: false,
+        },
+    };
+
+    eprintln!("Searching for naga v2 hyperspeed...");
+
+    let devices = match HidApi::new() {
+        Ok(api) => Devices {
+            api,
+            selection: Selection {
+                path: args.device,
+                serial: args.serial,
+            },
+            product_id: args.product_id,
+            policy: policy.clone(),
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match command {
+        MouseCommand::Apply {
+            changed_only,
+            verify,
+            transactional,
+            dry_run,
+        } => apply(
+            &devices,
+            &policy,
+            &args.config,
+            args.profile,
+            ApplyOptions {
+                changed_only,
+                verify,
+                transactional,
+                dry_run,
+            },
+        ),
+        MouseCommand::Get { button } => {
+            get(&devices, &policy, args.profile.unwrap_or_default(), button)
+        }
+        MouseCommand::Diff => diff(&devices, &policy, &args.config, args.profile),
+        MouseCommand::Dump { output } => dump(
+            &devices,
+            &policy,
+            args.profile.unwrap_or_default(),
+            output.as_deref(),
+        ),
+        MouseCommand::Dpi { set } => dpi(&devices, &policy, args.profile.unwrap_or_default(), set),
+        MouseCommand::List => list(&devices),
+        MouseCommand::Info => info(&devices, &policy),
+        MouseCommand::Battery { json } => battery(&devices, &policy, json),
+        MouseCommand::Power { idle, low_battery } => power(&devices, &policy, idle, low_battery),
+        MouseCommand::PollingRate { set } => polling_rate(&devices, &policy, set),
+        MouseCommand::DpiStages => dpi_stages(&devices, &policy, args.profile.unwrap_or_default()),
+        MouseCommand::Profile { switch_to, list } => profile(&devices, &policy, switch_to, list),
+    }
+}
+
+/// The HID devices sibaj looks at, narrowed down by `--device` and `--serial`.
+struct Devices {
+    api: HidApi,
+    selection: Selection,
+    /// Taken to be the naga too, see `Connection::detect`.
+    product_id: Option<u16>,
+    /// For asking the mouse its serial number when the HID device doesn't have one.
+    policy: RetryPolicy,
+}
+
+impl Devices {
+    /// Every interface of every naga and how it's attached, narrowed down by `--device`.
+    fn nagas(&self) -> impl Iterator<Item = (&DeviceInfo, Connection)> {
+        self.api
+            .device_list()
+            .filter(|device| {
+                device.vendor_id() == RAZER_VENDOR_ID
+                    && self.selection.wants_path(&device.path().to_string_lossy())
+            })
+            .filter_map(|device| {
+                Connection::detect(device.bus_type(), device.product_id(), self.product_id)
+                    .map(|connection| (device, connection))
+            })
+    }
+
+    /// The control interface of every naga, narrowed down by `--device` and `--serial`.
+    fn candidates(&self) -> Result<Vec<(&DeviceInfo, Connection)>, SelectError> {
+        let mut nagas = self.nagas().peekable();
+        if nagas.peek().is_none() {
+            return Err(SelectError::NotFound);
+        }
+        let mut controls = Vec::new();
+        let mut open_error = None;
+        for (device, connection) in nagas {
+            match is_control_interface(&self.api, device) {
+                Ok(true) => controls.push((device, connection)),
+                Ok(false) => {}
+                // Only matters if it keeps us from finding any control interface at all.
+                Err(e) => open_error = open_error.or(Some(e)),
+            }
+        }
+        if controls.is_empty() {
+            return Err(open_error.unwrap_or(SelectError::NoControlInterface));
+        }
+        let selected: Vec<_> = controls
+            .into_iter()
+            .filter(|(device, _)| self.selection.wants_serial(|| self.serial_of(device)))
+            .collect();
+        if selected.is_empty() {
+            return Err(SelectError::NotFound);
+        }
+        Ok(selected)
+    }
+
+    fn open_mouse(&self, device: &DeviceInfo) -> HidResult<HidDevice> {
+        self.api.open_path(device.path())
+    }
+
+    /// Opens the one candidate, printing why if there isn't exactly one.
+    fn open(&self) -> Option<HidDevice> {
+        let picked = self.candidates().and_then(|candidates| {
+            pick_one(candidates, |(device, _)| {
+                let serial = self.serial_of(device);
+                format!(
+                    "{} (serial {})",
+                    device.path().to_string_lossy(),
+                    serial.as_deref().unwrap_or("unknown")
+                )
+            })
+        });
+        let (device, _) = match picked {
+            Ok(picked) => picked,
+            Err(e) => {
+                eprintln!("{}", e);
+                return None;
+            }
+        };
+        eprintln!("path: {}", device.path().to_string_lossy());
+        match self.open_mouse(device) {
+            Ok(mousey) => Some(mousey),
+            Err(e) => {
+                eprintln!("Unable to open {}: {}", device.path().to_string_lossy(), e);
+                None
+            }
+        }
+    }
+
+    /// Runs `command` against the one candidate. `None` if there wasn't one.
+    fn with_mouse<T>(
+        &self,
+        command: impl Fn(&HidDevice) -> Result<T, CommandError>,
+    ) -> Option<Result<T, CommandError>> {
+        self.open().map(|mousey| command(&mousey))
+    }
+
+    /// The serial number HID reports for `device`, or failing that the one the mouse itself reports. The dongle
+    /// seems to leave the HID one empty.
+    fn serial_of(&self, device: &DeviceInfo) -> Option<String> {
+        match device.serial_number() {
+            Some(serial) if !serial.is_empty() => Some(serial.to_owned()),
+            _ => {
+                let mousey = self.open_mouse(device).ok()?;
+                get_serial(&mousey, &self.policy).ok()
+            }
+        }
+    }
+}
+
+/// Reads the config file, with `--profile` taking the place of whatever profile it names.
+fn load_config(path: &Path, profile: Option<Profile>) -> Option<Config> {
+    match config::load_config(path) {
+        Ok(config) => Some(Config {
+            profile: profile.unwrap_or(config.profile),
+            ..config
+        }),
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// How `apply` goes about it.
+#[derive(Clone, Copy)]
+struct ApplyOptions {
+    changed_only: bool,
+    verify: bool,
+    transactional: bool,
+    dry_run: bool,
+}
+
+fn apply(
+    devices: &Devices,
+    policy: &RetryPolicy,
+    config: &Path,
+    profile: Option<Profile>,
+    options: ApplyOptions,
+) -> ExitCode {
+    let Some(config) = load_config(config, profile) else {
+        return ExitCode::FAILURE;
+    };
+
+    // Nothing to find out from the mouse, so no need to go looking for one.
+    if options.dry_run && !options.changed_only {
+        print_plan(&config.functions, &config);
+        return ExitCode::SUCCESS;
+    }
+
+    let Some(mousey) = devices.open() else {
+        return ExitCode::FAILURE;
+    };
+    apply_to(&mousey, policy, &config, options)
+}
+
+/// Everything `apply` does once it has a mouse to do it to.
+fn apply_to<D: FeatureReportDevice>(
+    mousey: &D,
+    policy: &RetryPolicy,
+    config: &Config,
+    options: ApplyOptions,
+) -> ExitCode {
+    let ApplyOptions {
+        changed_only,
+        verify,
+        transactional,
+        dry_run,
+    } = options;
+    let Config {
+        profile,
+        ref functions,
+        ..
+    } = *config;
+
+    let messages = if changed_only {
+        match changed_functions(mousey, profile, functions, policy) {
+            Ok(changed) => changed,
+            Err(e) => {
+                eprintln!("Unable to read the current bindings: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        functions.clone()
+    };
+
+    if dry_run {
+        print_plan(&messages, config);
+        return ExitCode::SUCCESS;
+    }
+
+    if transactional {
+        match apply_transaction(mousey, profile, &messages, policy, verify) {
+            Ok(()) => {}
+            Err(e) => {
+                report_transaction_error(&e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        match apply_functions(mousey, profile, &messages, policy) {
+            Ok(()) => {}
+            Err(failures) => {
+                for failure in &failures {
+                    eprintln!("{}", failure);
+                }
+                if let Some(last) = failures.last() {
+                    if matches!(last.error, CommandError::Send(_))
+                        && last.index + 1 < messages.len()
+                    {
+                        eprintln!(
+                            "The remaining {} weren't attempted.",
+                            messages.len() - last.index - 1
+                        );
                     }
+                }
+                return ExitCode::FAILURE;
+            }
+        }
 
-                    // Read messages from ron
-                    let mut data = String::new();
-                    let f = File::open("funcs.ron").expect("Unable to open file");
-                    let mut br = BufReader::new(f);
-                    br.read_to_string(&mut data).expect("Unable to read string");
-                    let messages: Vec<Function> = ron::from_str(&data).unwrap();
-
-                    // Read messages from json.
-                    // let file = File::open("funcs.json").unwrap();
-                    // let reader = BufReader::new(file);
-                    // let messages: Vec<Function> = serde_json::from_reader(reader).unwrap();
-
-                    // Write messages to json
-                    // let json = serde_json::to_string_pretty(&messages).unwrap();
-                    // let f = File::create("funcs.json").expect("Unable to create file");
-                    // let mut f = BufWriter::new(f);
-                    // f.write_all(json.as_bytes()).expect("Unable to write data");
-
-                    // Write messages to ron
-                    // let ron_pretty = PrettyConfig::new()
-                    //     .indentor("  ".into())
-                    //     .new_line("\n".into())
-                    //     .compact_arrays(true)
-                    //     .separate_tuple_members(false)
-                    //     .extensions(Extensions::UNWRAP_VARIANT_NEWTYPES);
-                    // let json = ron::ser::to_string_pretty(&messages, ron_pretty).unwrap();
-                    // let f = File::create("funcs.ron").expect("Unable to create file");
-                    // let mut f = BufWriter::new(f);
-                    // f.write_all(json.as_bytes()).expect("Unable to write data");
-
-                    println!("path: {}", device.path().to_string_lossy());
-                    let mousey = api.open_path(device.path()).unwrap();
-
-                    for msg in messages {
-                        let mut message = generate_message(&msg);
-                        if mousey.send_feature_report(&message).is_err() {
-                            // If we run into an error here, it typically means we have the wrong device, so try the next one.
-                            continue 'searchloop;
-                        }
-
-                        let mut buf = vec![0u8; 91];
-                        let _ = mousey.get_feature_report(&mut buf).unwrap();
-                        if buf[1] == 4 {
-                            // Four means the send report operation didn't work
-                            println!("Failed to send message. Make sure your mouse isn't asleep.");
-                            break 'searchloop;
-                        }
-                        message[1] = buf[1];
-                        assert_eq!(buf, message);
+        if verify {
+            match verify_functions(mousey, profile, functions, policy) {
+                Ok(mismatches) if mismatches.is_empty() => {}
+                Ok(mismatches) => {
+                    for mismatch in &mismatches {
+                        eprintln!("{}", mismatch);
                     }
+                    return ExitCode::FAILURE;
                 }
+                Err(e) => {
+                    eprintln!("Unable to verify the bindings: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+
+    if !apply_settings(mousey, config, policy, verify) {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// What `apply` would send, for `--dry-run`.
+fn print_plan(functions: &[Function], config: &Config) {
+    println!("Would assign in {}:", config.profile);
+    for func in functions {
+        println!("  {}", func);
+    }
+    if let Some(seconds) = config.idle_seconds {
+        println!("Would set the idle time to {} seconds", seconds);
+    }
+    if let Some(percent) = config.low_battery_percent {
+        println!("Would set the low battery threshold to {}%", percent);
+    }
+    if let Some(rate) = config.polling_rate {
+        println!("Would set the polling rate to {}", rate);
+    }
+    if let Some(stages) = &config.dpi_stages {
+        println!("Would set the DPI stages to:\n{}", stages);
+    }
+}
+
+/// Applies everything in the config besides the button bindings. False if any of it didn't work out, after saying why.
+fn apply_settings<D: FeatureReportDevice>(
+    mousey: &D,
+    config: &Config,
+    policy: &RetryPolicy,
+    verify: bool,
+) -> bool {
+    if let Some(seconds) = config.idle_seconds {
+        if let Err(e) = set_idle_seconds(mousey, seconds, policy) {
+            eprintln!("Unable to set the idle time: {}", e);
+            return false;
+        }
+    }
+    if let Some(percent) = config.low_battery_percent {
+        if let Err(e) = set_low_battery_percent(mousey, percent, policy) {
+            eprintln!("Unable to set the low battery threshold: {}", e);
+            return false;
+        }
+    }
+    if let Some(rate) = config.polling_rate {
+        // Always read back, see `set_polling_rate`.
+        if let Err(e) = set_polling_rate(mousey, rate, policy) {
+            eprintln!("Unable to set the polling rate: {}", e);
+            return false;
+        }
+    }
+    if let Some(stages) = &config.dpi_stages {
+        if let Err(e) = set_dpi_stages(mousey, config.profile, stages, policy) {
+            eprintln!("Unable to set the DPI stages: {}", e);
+            return false;
+        }
+        if verify {
+            match get_dpi_stages(mousey, config.profile, policy) {
+                Ok(actual) if actual == *stages => {}
+                Ok(actual) => {
+                    eprintln!("The DPI stages didn't take, the mouse has:\n{}", actual);
+                    return false;
+                }
+                Err(e) => {
+                    eprintln!("Unable to verify the DPI stages: {}", e);
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+fn report_transaction_error(error: &TransactionError) {
+    let (cause, rollback) = match error {
+        TransactionError::Snapshot(e) => {
+            eprintln!(
+                "Unable to read the current bindings, nothing was changed: {}",
+                e
+            );
+            return;
+        }
+        TransactionError::RolledBack { cause, rollback } => (cause, rollback),
+    };
+    match cause {
+        RollbackCause::Apply(failures) => {
+            for failure in failures {
+                eprintln!("{}", failure);
+            }
+        }
+        RollbackCause::Verify(mismatches) => {
+            for mismatch in mismatches {
+                eprintln!("{}", mismatch);
+            }
+        }
+        RollbackCause::VerifyRead(e) => eprintln!("Unable to verify the bindings: {}", e),
+    }
+    match rollback {
+        Ok(()) => eprintln!("Rolled back, the buttons are assigned the way they were before."),
+        Err(failures) => {
+            eprintln!("Rolling back failed too, the mouse is left partly changed:");
+            for failure in failures {
+                eprintln!("{}", failure);
+            }
+        }
+    }
+}
+
+fn get(
+    devices: &Devices,
+    policy: &RetryPolicy,
+    profile: Profile,
+    button: Option<MouseButton>,
+) -> ExitCode {
+    let bindings = devices.with_mouse(|mousey| match button {
+        Some(button) => get_bindings(
+            mousey,
+            profile,
+            &Layer::ALL.map(|layer| (button, layer)),
+            policy,
+        ),
+        None => all_bindings(mousey, profile, policy),
+    });
+    match bindings {
+        Some(Ok(bindings)) => {
+            for func in bindings {
+                println!("{}", func);
             }
+            ExitCode::SUCCESS
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
         }
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn dump(
+    devices: &Devices,
+    policy: &RetryPolicy,
+    profile: Profile,
+    output: Option<&Path>,
+) -> ExitCode {
+    let Some(mousey) = devices.open() else {
+        return ExitCode::FAILURE;
+    };
+    dump_from(&mousey, policy, profile, output)
+}
+
+/// Writes out the bindings in `profile` along with which profile they came from, so applying the dump puts them back
+/// where they were.
+fn dump_from<D: FeatureReportDevice>(
+    mousey: &D,
+    policy: &RetryPolicy,
+    profile: Profile,
+    output: Option<&Path>,
+) -> ExitCode {
+    let functions = match all_bindings(mousey, profile, policy) {
+        Ok(functions) => functions,
         Err(e) => {
-            eprintln!("Error: {}", e);
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let dumped = Config {
+        profile,
+        functions,
+        ..Config::default()
+    };
+    let written = match output {
+        Some(path) => config::save_config(path, &dumped),
+        None => config::config_to_string(&dumped, false).map(|ron| println!("{}", ron)),
+    };
+    if let Err(e) = written {
+        eprintln!("Unable to write bindings: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn diff(
+    devices: &Devices,
+    policy: &RetryPolicy,
+    config: &Path,
+    profile: Option<Profile>,
+) -> ExitCode {
+    let Some(Config {
+        profile,
+        functions: wanted,
+        ..
+    }) = load_config(config, profile)
+    else {
+        return ExitCode::FAILURE;
+    };
+    let keys = diff::keys(&wanted);
+    let current = match devices.with_mouse(|mousey| get_bindings(mousey, profile, &keys, policy)) {
+        Some(Ok(current)) => current,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        None => return ExitCode::FAILURE,
+    };
+
+    let changes = diff::diff(&current, &wanted);
+    let (mut added, mut changed, mut unchanged) = (0, 0, 0);
+    for change in &changes {
+        println!("{}", change);
+        match change {
+            BindingChange::Added(_) => added += 1,
+            BindingChange::Changed { .. } => changed += 1,
+            BindingChange::Unchanged(_) => unchanged += 1,
+        }
+    }
+    println!(
+        "{} added, {} changed, {} unchanged",
+        added, changed, unchanged
+    );
+    ExitCode::SUCCESS
+}
+
+fn profile(
+    devices: &Devices,
+    policy: &RetryPolicy,
+    switch_to: Option<Profile>,
+    list: bool,
+) -> ExitCode {
+    let result = devices.with_mouse(|mousey| {
+        if let Some(profile) = switch_to {
+            set_active_profile(mousey, profile, policy)?;
+        }
+        let active = get_active_profile(mousey, policy)?;
+        let available = if list {
+            available_profiles(mousey, policy)?
+        } else {
+            vec![]
+        };
+        Ok((active, available))
+    });
+    match result {
+        Some(Ok((active, _))) if !list => {
+            println!("{}", active);
+            ExitCode::SUCCESS
+        }
+        Some(Ok((active, available))) => {
+            for profile in available {
+                let marker = if profile == active { "*" } else { " " };
+                println!("{} {}", marker, profile);
+            }
+            ExitCode::SUCCESS
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn dpi(devices: &Devices, policy: &RetryPolicy, profile: Profile, set: Option<Dpi>) -> ExitCode {
+    let result = devices.with_mouse(|mousey| {
+        if let Some(dpi) = set {
+            set_dpi(mousey, profile, dpi, policy)?;
+        }
+        get_dpi(mousey, profile, policy)
+    });
+    match result {
+        Some(Ok(dpi)) => {
+            println!("{}", dpi);
+            ExitCode::SUCCESS
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn list(devices: &Devices) -> ExitCode {
+    let candidates = match devices.candidates() {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    for (device, connection) in candidates {
+        println!("{}", device.path().to_string_lossy());
+        match devices.serial_of(device) {
+            Some(serial) => println!("  serial:     {}", serial),
+            None => println!("  serial:     unknown"),
+        }
+        println!("  interface:  {}", device.interface_number());
+        println!("  connection: {}", connection);
+    }
+    ExitCode::SUCCESS
+}
+
+fn validate(path: &Path) -> ExitCode {
+    let Some(config) = load_config(path, None) else {
+        return ExitCode::FAILURE;
+    };
+    println!(
+        "{} is fine: {} functions for {}",
+        path.display(),
+        config.functions.len(),
+        config.profile
+    );
+    ExitCode::SUCCESS
+}
+
+fn convert(input: &Path, output: &Path) -> ExitCode {
+    let Some(config) = load_config(input, None) else {
+        return ExitCode::FAILURE;
+    };
+    if let Err(e) = config::save_config(output, &config) {
+        eprintln!("Unable to write {}: {}", output.display(), e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn info(devices: &Devices, policy: &RetryPolicy) -> ExitCode {
+    let candidates = match devices.candidates() {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Every candidate is described even if one can't be, but the exit code says so.
+    let mut failed = false;
+    for (device, connection) in candidates {
+        let path = device.path().to_string_lossy();
+        println!("{}", path);
+        println!(
+            "  VID/PID:    {:04x}:{:04x}",
+            device.vendor_id(),
+            device.product_id()
+        );
+        println!("  interface:  {}", device.interface_number());
+        println!("  connection: {}", connection);
+        let mousey = match devices.open_mouse(device) {
+            Ok(mousey) => mousey,
+            Err(e) => {
+                eprintln!("Unable to open {}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        match get_firmware_version(&mousey, policy) {
+            Ok(firmware) => println!("  firmware:   {}", firmware),
+            Err(e) => {
+                eprintln!("Unable to read the firmware version of {}: {}", path, e);
+                failed = true;
+            }
+        }
+        match get_serial(&mousey, policy) {
+            Ok(serial) => println!("  serial:     {}", serial),
+            Err(e) => {
+                eprintln!("Unable to read the serial number of {}: {}", path, e);
+                failed = true;
+            }
+        }
+        match get_device_mode(&mousey, policy) {
+            Ok(mode) => println!("  mode:       {}", mode),
+            Err(e) => {
+                eprintln!("Unable to read the device mode of {}: {}", path, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn battery(devices: &Devices, policy: &RetryPolicy, json: bool) -> ExitCode {
+    match devices.with_mouse(|mousey| get_battery(mousey, policy)) {
+        Some(Ok(battery)) if json => match serde_json::to_string(&battery) {
+            Ok(json) => {
+                println!("{}", json);
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Unable to write JSON: {}", e);
+                ExitCode::FAILURE
+            }
+        },
+        Some(Ok(battery)) => {
+            println!("{}", battery);
+            ExitCode::SUCCESS
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn power(
+    devices: &Devices,
+    policy: &RetryPolicy,
+    idle: Option<u16>,
+    low_battery: Option<u8>,
+) -> ExitCode {
+    let result = devices.with_mouse(|mousey| {
+        if let Some(seconds) = idle {
+            set_idle_seconds(mousey, seconds, policy)?;
+        }
+        if let Some(percent) = low_battery {
+            set_low_battery_percent(mousey, percent, policy)?;
+        }
+        Ok((
+            get_idle_seconds(mousey, policy)?,
+            get_low_battery_percent(mousey, policy)?,
+        ))
+    });
+    match result {
+        Some(Ok((idle, low_battery))) => {
+            println!("Sleeps after {} seconds idle", idle);
+            println!("Low power mode at {}% battery", low_battery);
+            ExitCode::SUCCESS
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn polling_rate(devices: &Devices, policy: &RetryPolicy, set: Option<PollingRate>) -> ExitCode {
+    let result = devices.with_mouse(|mousey| match set {
+        Some(rate) => set_polling_rate(mousey, rate, policy).map(|()| rate),
+        None => get_polling_rate(mousey, policy),
+    });
+    match result {
+        Some(Ok(rate)) => {
+            println!("{}", rate);
+            ExitCode::SUCCESS
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn dpi_stages(devices: &Devices, policy: &RetryPolicy, profile: Profile) -> ExitCode {
+    match devices.with_mouse(|mousey| get_dpi_stages(mousey, profile, policy)) {
+        Some(Ok(stages)) => {
+            println!("{}", stages);
+            ExitCode::SUCCESS
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        apply_to, dump_from, parse_dpi, parse_polling_rate, parse_product_id, parse_profile,
+        parse_slot, ApplyOptions, Args, Command, MouseCommand,
+    };
+    use crate::config::{load_config, Config};
+    use crate::device::{apply_functions, get_idle_seconds, RetryPolicy};
+    use crate::emulator::EmulatedNaga;
+    use crate::model::{funcs_ron, Profile};
+    use crate::settings::{Dpi, PollingRate};
+    use clap::{CommandFactory, Parser};
+    use std::env;
+    use std::fs;
+    use std::process::ExitCode;
+
+    #[test]
+    fn parse_arguments() {
+        assert_eq!(parse_profile("3"), Ok(Profile::Slot(3)));
+        assert_eq!(parse_profile("Volatile"), Ok(Profile::Volatile));
+        assert!(parse_profile("0").is_err());
+        assert!(parse_profile("6").is_err());
+        assert!(parse_slot("volatile").is_err());
+
+        assert_eq!(parse_dpi("1600"), Ok(Dpi { x: 1600, y: 1600 }));
+        assert_eq!(parse_dpi("1600x800"), Ok(Dpi { x: 1600, y: 800 }));
+        assert!(parse_dpi("1600x").is_err());
+        assert!(parse_dpi("50").is_err());
+
+        assert_eq!(parse_polling_rate("500"), Ok(PollingRate::Hz500));
+        assert_eq!(parse_polling_rate("1000Hz"), Ok(PollingRate::Hz1000));
+        assert!(parse_polling_rate("300").is_err());
+
+        assert_eq!(parse_product_id("0x00b4"), Ok(0x00b4));
+        assert_eq!(parse_product_id("b5"), Ok(0x00b5));
+        assert!(parse_product_id("0xgg").is_err());
+        assert!(parse_product_id("10000").is_err());
+    }
+
+    #[test]
+    fn command_line() {
+        Args::command().debug_assert();
+
+        let args = Args::try_parse_from(["sibaj"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.config.to_str(), Some("funcs.ron"));
+
+        // Global flags work after the subcommand too.
+        let args =
+            Args::try_parse_from(["sibaj", "diff", "--config", "x.ron", "--profile", "2"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Mouse(MouseCommand::Diff))
+        ));
+        assert_eq!(args.config.to_str(), Some("x.ron"));
+        assert_eq!(args.profile, Some(Profile::Slot(2)));
+
+        let args = Args::try_parse_from(["sibaj", "validate"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Validate)));
+        let args = Args::try_parse_from(["sibaj", "convert", "a.ron", "b.json"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Convert { input, output }) if input.to_str() == Some("a.ron") && output.to_str() == Some("b.json")
+        ));
+        assert!(Args::try_parse_from(["sibaj", "convert", "a.ron"]).is_err());
+    }
+
+    #[test]
+    fn apply_changed_only() {
+        // No retries, so a failed write can't be papered over.
+        let policy = RetryPolicy {
+            retries: 0,
+            ..RetryPolicy::default()
+        };
+        let functions = funcs_ron();
+        let options = ApplyOptions {
+            changed_only: true,
+            verify: true,
+            transactional: false,
+            dry_run: false,
+        };
+        let naga = EmulatedNaga::new();
+        let config = Config {
+            functions,
+            idle_seconds: Some(120),
+            ..Config::default()
+        };
+        assert_eq!(
+            apply_to(&naga, &policy, &config, options),
+            ExitCode::SUCCESS
+        );
+
+        // Nothing has changed, so nothing is sent that could fail.
+        naga.set_fail_write(0);
+        assert_eq!(
+            apply_to(&naga, &policy, &config, options),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(get_idle_seconds(&naga, &policy).unwrap(), 120);
+
+        // The bindings are fine, but a setting the mouse refuses still fails the whole thing.
+        let config = Config {
+            idle_seconds: Some(30),
+            ..config
+        };
+        assert_eq!(
+            apply_to(&naga, &policy, &config, options),
+            ExitCode::FAILURE
+        );
+    }
+
+    #[test]
+    fn dump_keeps_profile() {
+        let policy = RetryPolicy::no_wait(0);
+        let functions = funcs_ron();
+        let naga = EmulatedNaga::new();
+        apply_functions(&naga, Profile::Slot(3), &functions, &policy).unwrap();
+
+        let path = env::temp_dir().join(format!("sibaj-dump-{}.ron", std::process::id()));
+        let dumped = dump_from(&naga, &policy, Profile::Slot(3), Some(&path));
+        let loaded = load_config(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dumped, ExitCode::SUCCESS);
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.profile, Profile::Slot(3));
+        for func in &functions {
+            assert!(loaded.functions.contains(func));
         }
     }
 }
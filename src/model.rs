@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct Function {
     pub(crate) button: MouseButton,
+    /// Which layer the action is on, i.e. whether it's what the button does normally or while Hypershift is held.
+    #[serde(default, skip_serializing_if = "Layer::is_default")]
+    pub(crate) layer: Layer,
     pub(crate) action: Action,
 }
 
@@ -21,6 +25,7 @@ impl Function {
     pub(crate) fn generate_parameter_string(&self) -> [u8; 9] {
         let mut string = [0u8; 9];
         string[0] = self.button as _;
+        string[1] = self.layer as _;
 
         match &self.action {
             Action::Disable => {
@@ -94,6 +99,150 @@ impl Function {
         }
         string
     }
+
+    /// The button and layer this function is stored under on the mouse. That's usually just its button and layer, except
+    /// the Hypershift key itself, which Synapse always sends with the layer byte set. My guess is that's how the mouse
+    /// knows to keep it held down while in the Hypershift layer.
+    pub(crate) fn key(&self) -> (MouseButton, Layer) {
+        match self.action {
+            Action::Hypershift => (self.button, Layer::Hypershift),
+            _ => (self.button, self.layer),
+        }
+    }
+
+    /// The inverse of `generate_parameter_string`.
+    pub(crate) fn from_parameter_string(string: &[u8; 9]) -> Result<Self, DecodeError> {
+        let button = MouseButton::try_from(string[0])?;
+        let mut layer = Layer::try_from(string[1])?;
+        let action = match (string[2], string[3]) {
+            (0x00, 0x00) => Action::Disable,
+            (0x0c, 0x01) if string[1] == 0x01 && string[4] == 0x01 => {
+                // See `key`, the layer byte is part of the action here.
+                layer = Layer::Base;
+                Action::Hypershift
+            }
+            (0x01, 0x01) => Action::Mouse(ButtonConfig {
+                button: MouseButton::try_from(string[4])?,
+                interval_ms: 0,
+            }),
+            (0x0e, 0x03) => Action::Mouse(ButtonConfig {
+                button: MouseButton::try_from(string[4])?,
+                interval_ms: u16::from_be_bytes([string[5], string[6]]),
+            }),
+            (0x02, 0x02) => Action::Keyboard(KeyPress {
+                key: UsbKbScanCode::try_from(string[5])?,
+                modifiers: KeyMod::from_bits(string[4]),
+                interval_ms: 0,
+            }),
+            (0x0d, 0x04) => Action::Keyboard(KeyPress {
+                key: UsbKbScanCode::try_from(string[5])?,
+                modifiers: KeyMod::from_bits(string[4]),
+                interval_ms: u16::from_be_bytes([string[6], string[7]]),
+            }),
+            (0x06, 0x05) if string[4] == 0x05 => {
+                Action::Sensitivity(SensitivityFunction::Clutch(SensitivityClutch {
+                    x: u16::from_be_bytes([string[5], string[6]]),
+                    y: u16::from_be_bytes([string[7], string[8]]),
+                }))
+            }
+            (0x06, 0x01) => Action::Sensitivity(match string[4] {
+                0x01 => SensitivityFunction::StageUp,
+                0x02 => SensitivityFunction::StageDown,
+                0x06 => SensitivityFunction::CycleUpStage,
+                0x07 => SensitivityFunction::CycleDownStage,
+                other => return Err(DecodeError::Sensitivity(other)),
+            }),
+            (kind, len) => return Err(DecodeError::ActionType { kind, len }),
+        };
+        Ok(Function {
+            button,
+            layer,
+            action,
+        })
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.layer {
+            Layer::Base => write!(f, "{:?}: {}", self.button, self.action),
+            Layer::Hypershift => write!(f, "Hypershift+{:?}: {}", self.button, self.action),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let interval_ms = match self {
+            Action::Disable => return f.write_str("disabled"),
+            Action::Hypershift => return f.write_str("hypershift"),
+            Action::Sensitivity(SensitivityFunction::Clutch(sc)) => {
+                return write!(f, "sensitivity clutch at {}x{} DPI", sc.x, sc.y)
+            }
+            Action::Sensitivity(s_func) => return write!(f, "sensitivity {:?}", s_func),
+            Action::Mouse(emulate_button) => {
+                write!(f, "{:?}", emulate_button.button)?;
+                emulate_button.interval_ms
+            }
+            Action::Keyboard(keyboard_function) => {
+                for modifier in &keyboard_function.modifiers {
+                    write!(f, "{:?}+", modifier)?;
+                }
+                write!(f, "{:?}", keyboard_function.key)?;
+                keyboard_function.interval_ms
+            }
+        };
+        if interval_ms > 0 {
+            write!(f, " every {} ms", interval_ms)?;
+        }
+        Ok(())
+    }
+}
+
+/// Two functions are the same if the mouse can't tell them apart, e.g. the order modifiers are listed in doesn't matter.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.generate_parameter_string() == other.generate_parameter_string()
+    }
+}
+
+impl Eq for Function {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DecodeError {
+    Button(u8),
+    Key(u8),
+    Sensitivity(u8),
+    ActionType { kind: u8, len: u8 },
+    Command { class: u8, id: u8 },
+    Profile(u8),
+    Layer(u8),
+    DpiStages(u8),
+    PollingRate(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Button(b) => write!(f, "Unknown mouse button {:#04x}", b),
+            DecodeError::Key(k) => write!(f, "Unknown keyboard scan code {:#04x}", k),
+            DecodeError::Sensitivity(s) => write!(f, "Unknown sensitivity function {:#04x}", s),
+            DecodeError::ActionType { kind, len } => write!(
+                f,
+                "Unknown action type {:#04x} with {} parameter bytes",
+                kind, len
+            ),
+            DecodeError::Command { class, id } => write!(
+                f,
+                "Unexpected response command (class {:#04x}, id {:#04x})",
+                class, id
+            ),
+            DecodeError::Profile(p) => write!(f, "Unknown profile {:#04x}", p),
+            DecodeError::Layer(l) => write!(f, "Unknown layer {:#04x}", l),
+            DecodeError::DpiStages(count) => write!(f, "Too many DPI stages: {}", count),
+            DecodeError::PollingRate(rate) => write!(f, "Unknown polling rate {:#04x}", rate),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -105,7 +254,7 @@ pub(crate) struct ButtonConfig {
     interval_ms: u16,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[repr(u8)]
 pub(crate) enum MouseButton {
     LClick = 0x01,
@@ -133,6 +282,118 @@ pub(crate) enum MouseButton {
     Side12 = 0x4b,
 }
 
+impl MouseButton {
+    pub(crate) const ALL: [MouseButton; 23] = [
+        MouseButton::LClick,
+        MouseButton::RClick,
+        MouseButton::MClick,
+        MouseButton::Mouse4,
+        MouseButton::Mouse5,
+        MouseButton::UScroll,
+        MouseButton::DScroll,
+        MouseButton::SenStageUp,
+        MouseButton::SenStageDown,
+        MouseButton::LScroll,
+        MouseButton::RScroll,
+        MouseButton::Side1,
+        MouseButton::Side2,
+        MouseButton::Side3,
+        MouseButton::Side4,
+        MouseButton::Side5,
+        MouseButton::Side6,
+        MouseButton::Side7,
+        MouseButton::Side8,
+        MouseButton::Side9,
+        MouseButton::Side10,
+        MouseButton::Side11,
+        MouseButton::Side12,
+    ];
+}
+
+impl TryFrom<u8> for MouseButton {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        MouseButton::ALL
+            .into_iter()
+            .find(|b| *b as u8 == value)
+            .ok_or(DecodeError::Button(value))
+    }
+}
+
+/// Every button has a second action it does while the Hypershift key is held.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[repr(u8)]
+pub(crate) enum Layer {
+    #[default]
+    Base = 0x00,
+    Hypershift = 0x01,
+}
+
+impl Layer {
+    pub(crate) const ALL: [Layer; 2] = [Layer::Base, Layer::Hypershift];
+}
+
+impl TryFrom<u8> for Layer {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Layer::ALL
+            .into_iter()
+            .find(|l| *l as u8 == value)
+            .ok_or(DecodeError::Layer(value))
+    }
+}
+
+/// Where a report's settings go. This is the first argument byte of every assignment report. Synapse always sends 0x01
+/// there, and 0x00 seems to apply things without saving them, so they're gone once the mouse is power cycled. 0x02 and
+/// up look like the other onboard slots, though I've only ever seen the mouse accept up to 5.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub(crate) enum Profile {
+    /// Applied right away but not saved to the mouse.
+    Volatile,
+    /// One of the onboard slots, numbered 1 to 5. Slot 1 is the one Synapse uses.
+    Slot(u8),
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Slot(1)
+    }
+}
+
+impl Profile {
+    pub(crate) const SLOTS: u8 = 5;
+
+    pub(crate) fn byte(self) -> u8 {
+        match self {
+            Profile::Volatile => 0x00,
+            Profile::Slot(n) => n,
+        }
+    }
+}
+
+impl TryFrom<u8> for Profile {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Profile::Volatile),
+            1..=Profile::SLOTS => Ok(Profile::Slot(value)),
+            _ => Err(DecodeError::Profile(value)),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Volatile => write!(f, "volatile"),
+            Profile::Slot(n) => write!(f, "profile {}", n),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub(crate) struct SensitivityClutch {
     x: u16,
@@ -158,6 +419,12 @@ impl IsDefault for u16 {
     }
 }
 
+impl IsDefault for Layer {
+    fn is_default(&self) -> bool {
+        *self == Layer::Base
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct KeyPress {
     pub(crate) key: UsbKbScanCode,
@@ -182,6 +449,27 @@ pub(crate) enum KeyMod {
     RGui = 0x80,
 }
 
+impl KeyMod {
+    const ALL: [KeyMod; 8] = [
+        KeyMod::LControl,
+        KeyMod::LShift,
+        KeyMod::LAlt,
+        KeyMod::LGui,
+        KeyMod::RControl,
+        KeyMod::RShift,
+        KeyMod::RAlt,
+        KeyMod::RGui,
+    ];
+
+    /// Every bit of the modifier byte is a modifier, so there's nothing here that can fail.
+    fn from_bits(bits: u8) -> Vec<KeyMod> {
+        KeyMod::ALL
+            .into_iter()
+            .filter(|m| bits & *m as u8 != 0)
+            .collect()
+    }
+}
+
 // Source: https://download.microsoft.com/download/1/6/1/161ba512-40e2-4cc9-843a-923143f3456c/scancode.doc
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 #[repr(u8)]
@@ -364,6 +652,195 @@ pub(crate) enum UsbKbScanCode {
     // 232-255	E8-FF	Reserved
 }
 
+impl UsbKbScanCode {
+    const ALL: [UsbKbScanCode; 173] = [
+        UsbKbScanCode::Disabled,
+        UsbKbScanCode::KeyboardErrorRollOver,
+        UsbKbScanCode::KbPOSTFail,
+        UsbKbScanCode::KbErrorUndefined,
+        UsbKbScanCode::KbA,
+        UsbKbScanCode::KbB,
+        UsbKbScanCode::KbC,
+        UsbKbScanCode::KbD,
+        UsbKbScanCode::KbE,
+        UsbKbScanCode::KbF,
+        UsbKbScanCode::KbG,
+        UsbKbScanCode::KbH,
+        UsbKbScanCode::KbI,
+        UsbKbScanCode::KbJ,
+        UsbKbScanCode::KbK,
+        UsbKbScanCode::KbL,
+        UsbKbScanCode::KbM,
+        UsbKbScanCode::KbN,
+        UsbKbScanCode::KbO,
+        UsbKbScanCode::KbP,
+        UsbKbScanCode::KbQ,
+        UsbKbScanCode::KbR,
+        UsbKbScanCode::KbS,
+        UsbKbScanCode::KbT,
+        UsbKbScanCode::KbU,
+        UsbKbScanCode::KbV,
+        UsbKbScanCode::KbW,
+        UsbKbScanCode::KbX,
+        UsbKbScanCode::KbY,
+        UsbKbScanCode::KbZ,
+        UsbKbScanCode::Kb1,
+        UsbKbScanCode::Kb2,
+        UsbKbScanCode::Kb3,
+        UsbKbScanCode::Kb4,
+        UsbKbScanCode::Kb5,
+        UsbKbScanCode::Kb6,
+        UsbKbScanCode::Kb7,
+        UsbKbScanCode::Kb8,
+        UsbKbScanCode::Kb9,
+        UsbKbScanCode::Kb0,
+        UsbKbScanCode::KbEnter,
+        UsbKbScanCode::KbEscape,
+        UsbKbScanCode::KbBackspace,
+        UsbKbScanCode::KbTab,
+        UsbKbScanCode::KbSpacebar,
+        UsbKbScanCode::KbMinus,
+        UsbKbScanCode::KbEquals,
+        UsbKbScanCode::KbLbracket,
+        UsbKbScanCode::KbRbracket,
+        UsbKbScanCode::KbBackslash,
+        UsbKbScanCode::KbNonUSTilde,
+        UsbKbScanCode::Keybard,
+        UsbKbScanCode::KbQuote,
+        UsbKbScanCode::KbGrave,
+        UsbKbScanCode::KbComma,
+        UsbKbScanCode::KbDot,
+        UsbKbScanCode::KbSlash,
+        UsbKbScanCode::KbCapsLock,
+        UsbKbScanCode::KbF1,
+        UsbKbScanCode::KbF2,
+        UsbKbScanCode::KbF3,
+        UsbKbScanCode::KbF4,
+        UsbKbScanCode::KbF5,
+        UsbKbScanCode::KbF6,
+        UsbKbScanCode::KbF7,
+        UsbKbScanCode::KbF8,
+        UsbKbScanCode::KbF9,
+        UsbKbScanCode::KbF10,
+        UsbKbScanCode::KbF11,
+        UsbKbScanCode::KbF12,
+        UsbKbScanCode::KbPrintScreen,
+        UsbKbScanCode::KbScrollLock,
+        UsbKbScanCode::KbPause,
+        UsbKbScanCode::KbInsert,
+        UsbKbScanCode::KbHome,
+        UsbKbScanCode::KbPageUp,
+        UsbKbScanCode::KbDelete,
+        UsbKbScanCode::KbEnd,
+        UsbKbScanCode::KbPageDown,
+        UsbKbScanCode::KbRightArrow,
+        UsbKbScanCode::KbLeftArrow,
+        UsbKbScanCode::KbDownArrow,
+        UsbKbScanCode::KbUpArrow,
+        UsbKbScanCode::KeypadNumLock,
+        UsbKbScanCode::KeypadSlash,
+        UsbKbScanCode::KeypadAsterisk,
+        UsbKbScanCode::KeypadMinus,
+        UsbKbScanCode::KeypadPlus,
+        UsbKbScanCode::KeypadEnter,
+        UsbKbScanCode::Keypad1,
+        UsbKbScanCode::Keypad2,
+        UsbKbScanCode::Keypad3,
+        UsbKbScanCode::Keypad4,
+        UsbKbScanCode::Keypad5,
+        UsbKbScanCode::Keypad6,
+        UsbKbScanCode::Keypad7,
+        UsbKbScanCode::Keypad8,
+        UsbKbScanCode::Keypad9,
+        UsbKbScanCode::Keypad0,
+        UsbKbScanCode::KeypadDot,
+        UsbKbScanCode::KbNonUSBackslash,
+        UsbKbScanCode::KbApplication,
+        UsbKbScanCode::KbPower,
+        UsbKbScanCode::KeypadEquals,
+        UsbKbScanCode::KbF13,
+        UsbKbScanCode::KbF14,
+        UsbKbScanCode::KbF15,
+        UsbKbScanCode::KbF16,
+        UsbKbScanCode::KbF17,
+        UsbKbScanCode::KbF18,
+        UsbKbScanCode::KbF19,
+        UsbKbScanCode::KbF20,
+        UsbKbScanCode::KbF21,
+        UsbKbScanCode::KbF22,
+        UsbKbScanCode::KbF23,
+        UsbKbScanCode::KbF24,
+        UsbKbScanCode::KbExecute,
+        UsbKbScanCode::KbHelp,
+        UsbKbScanCode::KbMenu,
+        UsbKbScanCode::KbSelect,
+        UsbKbScanCode::KbStop,
+        UsbKbScanCode::KbAgain,
+        UsbKbScanCode::KbUndo,
+        UsbKbScanCode::KbCut,
+        UsbKbScanCode::KbCopy,
+        UsbKbScanCode::KbPaste,
+        UsbKbScanCode::KbFind,
+        UsbKbScanCode::KbMute,
+        UsbKbScanCode::KbVolumeUp,
+        UsbKbScanCode::KbVolumeDown,
+        UsbKbScanCode::KbLockingCapsLock12,
+        UsbKbScanCode::KbLockingNumLock12,
+        UsbKbScanCode::KbLockingScrollLock,
+        UsbKbScanCode::KeypadComma,
+        UsbKbScanCode::KeypadEqualSign,
+        UsbKbScanCode::KbKanji1,
+        UsbKbScanCode::KbKanji2,
+        UsbKbScanCode::KbKanji3,
+        UsbKbScanCode::KbKanji4,
+        UsbKbScanCode::KbKanji5,
+        UsbKbScanCode::KbKanji6,
+        UsbKbScanCode::KbKanji7,
+        UsbKbScanCode::KbKanji8,
+        UsbKbScanCode::KbKanji9,
+        UsbKbScanCode::KbLANG1,
+        UsbKbScanCode::KbLANG2,
+        UsbKbScanCode::KbLANG3,
+        UsbKbScanCode::KbLANG4,
+        UsbKbScanCode::KbLANG5,
+        UsbKbScanCode::KbLANG6,
+        UsbKbScanCode::KbLANG7,
+        UsbKbScanCode::KbLANG8,
+        UsbKbScanCode::KbLANG9,
+        UsbKbScanCode::KbAlternateErase,
+        UsbKbScanCode::KbSysReqAttenti,
+        UsbKbScanCode::KbCancel,
+        UsbKbScanCode::KbClear,
+        UsbKbScanCode::KbPrior,
+        UsbKbScanCode::KbReturn,
+        UsbKbScanCode::KbSeparator,
+        UsbKbScanCode::KbOut,
+        UsbKbScanCode::KbOper,
+        UsbKbScanCode::KbClearAgain,
+        UsbKbScanCode::KbCrSelProps,
+        UsbKbScanCode::KbExSel,
+        UsbKbScanCode::KbLControl,
+        UsbKbScanCode::KbLShift,
+        UsbKbScanCode::KbLAlt,
+        UsbKbScanCode::KbLGUI,
+        UsbKbScanCode::KbRControl,
+        UsbKbScanCode::KbRShift,
+        UsbKbScanCode::KbRAlt,
+        UsbKbScanCode::KbRGUI,
+    ];
+}
+
+impl TryFrom<u8> for UsbKbScanCode {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        UsbKbScanCode::ALL
+            .into_iter()
+            .find(|k| *k as u8 == value)
+            .ok_or(DecodeError::Key(value))
+    }
+}
+
 // 1.	Usage of keys is not modified by the state of the Control, Alt, Shift or Num Lock keys. That is, a key does not send extra codes to compensate for the state of any Control, Alt, Shift or Num Lock keys.
 // 2.	Typical language mappings: US: \| Belg: µ`£ FrCa: <}> Dan:’* Dutch: <> Fren:*µ Ger: #’ Ital: ù§ LatAm: }`] Nor:,* Span:}Ç Swed: ,* Swiss: $£ UK: #~.
 // 3.	Typical language mappings: Belg:<\> FrCa:«°» Dan:<\> Dutch:]|[ Fren:<> Ger:<|> Ital:<> LatAm:<> Nor:<> Span:<> Swed:<|> Swiss:<\> UK:\| Brazil: \|.
@@ -389,32 +866,35 @@ pub(crate) enum UsbKbScanCode {
 // 23.	Windowing environment key, examples are Microsoft Left Win key, Macintosh Left Apple key, Sun Left Meta key
 // 24.	Windowing environment key, examples are Microsoft Right Win key, Macintosh Right Apple key, Sun Right Meta key.
 
+/// The full report for a button assignment, as the tests below know them from captures.
+#[cfg(test)]
 pub(crate) fn generate_message(func: &Function) -> [u8; 91] {
-    let mut message = [0u8; 91];
-    // Byte number: Comment
-    // 0: Report ID: Needed strictly for the API, doesn't actually get sent to the mouse in this position, so everything else is basically off by one.
-    message[0] = 0;
-    // 1: Not sure what purpose this is for a send report (if any,) but on a get report it seems to be a status indicator
-    message[1] = 0;
-    // 2: Seems to be part of the checksum calculation. Basically this and byte 89 (byte 88 in the actual usb message) need to xor to zero or else the message is (I think) considered corrupted and is dropped.
-    message[2] = 0x1f;
-    // 3-6: Always seems to be zero. 3 is possibly extended checksum? Note the checksum byte at 89 is just before another zero byte.
-    // 6-9: No idea what any of this is. None of the numbers make any sense to me. All button assignment packets have these exact bytes. For everything else these are different.
-    message[6..=9].clone_from_slice(&[0x0a, 0x02, 0x0c, 0x01]);
-    // 10-18: Pretty much the meat of the payload. Basically tells the mouse what button to bind to what action.
-    let func_bytes = func.generate_parameter_string();
-    message[10..=18].clone_from_slice(&func_bytes);
-    // iterate through everything after the checksum seed and xor all bytes together
-    message[89] = message[3..].iter().fold(0, |acc, x| acc ^ x);
-    message
+    crate::report::RazerReport::from_function(func, Profile::default()).to_bytes()
+}
+
+/// The functions in funcs.ron, which most tests that need something to apply use.
+#[cfg(test)]
+pub(crate) fn funcs_ron() -> Vec<Function> {
+    ron::from_str(include_str!("../funcs.ron")).unwrap()
+}
+
+#[cfg(test)]
+pub(crate) fn disable(button: MouseButton) -> Function {
+    Function {
+        button,
+        layer: Layer::Base,
+        action: Action::Disable,
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::{
-        generate_message, Action, ButtonConfig, Function, KeyMod, KeyPress, MouseButton,
-        SensitivityClutch, SensitivityFunction, UsbKbScanCode,
+        disable, funcs_ron, generate_message, Action, ButtonConfig, DecodeError, Function, KeyMod,
+        KeyPress, Layer, MouseButton, Profile, SensitivityClutch, SensitivityFunction,
+        UsbKbScanCode,
     };
+    use crate::report::RazerReport;
     use hex_literal::hex;
 
     #[test]
@@ -422,6 +902,7 @@ mod test {
         let control = hex!("00001f0000000a020c010c0006010100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000f00");
         let test = Function {
             button: MouseButton::SenStageDown,
+            layer: Layer::Base,
             action: Action::Sensitivity(SensitivityFunction::StageUp),
         };
         assert_eq!(generate_message(&test), control);
@@ -429,6 +910,7 @@ mod test {
         let control = hex!("00001f0000000a020c010c0006010200000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c00");
         let test = Function {
             button: MouseButton::SenStageDown,
+            layer: Layer::Base,
             action: Action::Sensitivity(SensitivityFunction::StageDown),
         };
         assert_eq!(generate_message(&test), control);
@@ -436,6 +918,7 @@ mod test {
         let control = hex!("00001f0000000a020c010c0006010600000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000800");
         let test = Function {
             button: MouseButton::SenStageDown,
+            layer: Layer::Base,
             action: Action::Sensitivity(SensitivityFunction::CycleUpStage),
         };
         assert_eq!(generate_message(&test), control);
@@ -443,6 +926,7 @@ mod test {
         let control = hex!("00001f0000000a020c010c0006010700000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000900");
         let test = Function {
             button: MouseButton::SenStageDown,
+            layer: Layer::Base,
             action: Action::Sensitivity(SensitivityFunction::CycleDownStage),
         };
         assert_eq!(generate_message(&test), control);
@@ -450,6 +934,7 @@ mod test {
         let control = hex!("00001f0000000a020c010c0006050575300064000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002e00");
         let test = Function {
             button: MouseButton::SenStageDown,
+            layer: Layer::Base,
             action: Action::Sensitivity(SensitivityFunction::Clutch(SensitivityClutch {
                 x: 30000,
                 y: 100,
@@ -460,6 +945,7 @@ mod test {
         let control = hex!("00001f0000000a020c010c0006050575307530000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000f00");
         let test = Function {
             button: MouseButton::SenStageDown,
+            layer: Layer::Base,
             action: Action::Sensitivity(super::SensitivityFunction::Clutch(SensitivityClutch {
                 x: 30000,
                 y: 30000,
@@ -470,6 +956,7 @@ mod test {
         let control = hex!("00001f0000000a020c010c0006050503200320000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000f00");
         let test = Function {
             button: MouseButton::SenStageDown,
+            layer: Layer::Base,
             action: Action::Sensitivity(super::SensitivityFunction::Clutch(SensitivityClutch {
                 x: 800,
                 y: 800,
@@ -480,6 +967,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b0002022235000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000005900");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbGrave,
@@ -491,6 +979,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b0002020035000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007b00");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbGrave,
@@ -502,6 +991,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202702e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001000");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -513,6 +1003,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202ff2e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000009f00");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -533,6 +1024,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202402e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -544,6 +1036,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202042e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006400");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -555,6 +1048,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202102e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007000");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -566,6 +1060,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202202e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004000");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -577,6 +1072,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202022e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006200");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -588,6 +1084,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202002e000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbEquals,
@@ -597,15 +1094,13 @@ mod test {
         assert_eq!(generate_message(&test), control);
 
         let control = hex!("00001f0000000a020c014b0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004e00");
-        let test = Function {
-            button: MouseButton::Side12,
-            action: Action::Disable,
-        };
+        let test = disable(MouseButton::Side12);
         assert_eq!(generate_message(&test), control);
 
         let control = hex!("00001f0000000a020c01400002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004100");
         let test = Function {
             button: MouseButton::Side1,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -617,6 +1112,7 @@ mod test {
         let control = hex!("00001f0000000a020c014a0002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004b00");
         let test = Function {
             button: MouseButton::Side11,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -628,6 +1124,7 @@ mod test {
         let control = hex!("00001f0000000a020c014a000d040004003200000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007000");
         let test = Function {
             button: MouseButton::Side11,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 50,
                 key: UsbKbScanCode::KbA,
@@ -639,6 +1136,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b0002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004a00");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -650,6 +1148,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000202003a000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007400");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbF1,
@@ -661,6 +1160,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b0002020045000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000b00");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbF12,
@@ -672,6 +1172,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b0002020068000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002600");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbF13,
@@ -683,6 +1184,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b0002020073000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003d00");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbF24,
@@ -694,6 +1196,7 @@ mod test {
         let control = hex!("00001f0000000a020c01020002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -705,6 +1208,7 @@ mod test {
         let control = hex!("00001f0000000a020c01340002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003500");
         let test = Function {
             button: MouseButton::LScroll,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -716,6 +1220,7 @@ mod test {
         let control = hex!("00001f0000000a020c01350002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003400");
         let test = Function {
             button: MouseButton::RScroll,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -727,6 +1232,7 @@ mod test {
         let control = hex!("00001f0000000a020c01090002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000800");
         let test = Function {
             button: MouseButton::UScroll,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -738,6 +1244,7 @@ mod test {
         let control = hex!("00001f0000000a020c010a0002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000b00");
         let test = Function {
             button: MouseButton::DScroll,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -749,6 +1256,7 @@ mod test {
         let control = hex!("00001f0000000a020c01030002020004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000200");
         let test = Function {
             button: MouseButton::MClick,
+            layer: Layer::Base,
             action: Action::Keyboard(KeyPress {
                 interval_ms: 0,
                 key: UsbKbScanCode::KbA,
@@ -760,6 +1268,7 @@ mod test {
         let control = hex!("00001f0000000a020c014a0001010400000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004b00");
         let test = Function {
             button: MouseButton::Side11,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::Mouse4,
                 interval_ms: 0,
@@ -770,6 +1279,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b0001010500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004b00");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::Mouse5,
                 interval_ms: 0,
@@ -780,6 +1290,7 @@ mod test {
         let control = hex!("00001f0000000a020c014b000e030503e8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ad00");
         let test = Function {
             button: MouseButton::Side12,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::Mouse5,
                 interval_ms: 1000,
@@ -790,6 +1301,7 @@ mod test {
         let control = hex!("00001f0000000a020c01020001010200000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000500");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::RClick,
                 interval_ms: 0,
@@ -800,6 +1312,7 @@ mod test {
         let control = hex!("00001f0000000a020c0102000e030203e8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e300");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::RClick,
                 interval_ms: 1000,
@@ -810,6 +1323,7 @@ mod test {
         let control = hex!("00001f0000000a020c0102000e030201f4000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000fd00");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::RClick,
                 interval_ms: 500,
@@ -820,6 +1334,7 @@ mod test {
         let control = hex!("00001f0000000a020c0102000e0302014d0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004400");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::RClick,
                 interval_ms: 333,
@@ -830,6 +1345,7 @@ mod test {
         let control = hex!("00001f0000000a020c0102000e030200fa000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000f200");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::RClick,
                 interval_ms: 250,
@@ -840,6 +1356,7 @@ mod test {
         let control = hex!("00001f0000000a020c0102000e030200320000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003a00");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::RClick,
                 interval_ms: 50,
@@ -850,6 +1367,7 @@ mod test {
         let control = hex!("00001f0000000a020c01020001010100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600");
         let test = Function {
             button: MouseButton::RClick,
+            layer: Layer::Base,
             action: Action::Mouse(ButtonConfig {
                 button: MouseButton::LClick,
                 interval_ms: 0,
@@ -860,8 +1378,142 @@ mod test {
         let control = hex!("00001f0000000a020c0140010c010100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004800");
         let test = Function {
             button: MouseButton::Side1,
+            layer: Layer::Base,
             action: Action::Hypershift,
         };
         assert_eq!(generate_message(&test), control);
     }
+
+    #[test]
+    fn decode_round_trip() {
+        let examples: Vec<Function> = ron::from_str(include_str!("../examples.ron")).unwrap();
+        let funcs = funcs_ron();
+        for func in examples.iter().chain(&funcs) {
+            let string = func.generate_parameter_string();
+            let decoded = Function::from_parameter_string(&string).unwrap();
+            assert_eq!(decoded.generate_parameter_string(), string);
+
+            let report = RazerReport::from_function(func, Profile::default());
+            assert_eq!(report.to_function().unwrap(), *func);
+        }
+    }
+
+    #[test]
+    fn hypershift_layer() {
+        let shifted = Function {
+            button: MouseButton::Side2,
+            layer: Layer::Hypershift,
+            action: Action::Disable,
+        };
+        assert_eq!(shifted.generate_parameter_string()[1], 0x01);
+        assert_eq!(shifted.to_string(), "Hypershift+Side2: disabled");
+        assert_eq!(
+            Function::from_parameter_string(&shifted.generate_parameter_string()).unwrap(),
+            shifted
+        );
+
+        let key = Function {
+            button: MouseButton::Side1,
+            layer: Layer::Base,
+            action: Action::Hypershift,
+        };
+        assert_eq!(key.key(), (MouseButton::Side1, Layer::Hypershift));
+        let decoded = Function::from_parameter_string(&key.generate_parameter_string()).unwrap();
+        assert_eq!(decoded.layer, Layer::Base);
+        assert_eq!(
+            Function::from_parameter_string(&[0x40, 0x02, 0, 0, 0, 0, 0, 0, 0]).unwrap_err(),
+            DecodeError::Layer(0x02)
+        );
+    }
+
+    #[test]
+    fn decode_modifiers_and_turbo() {
+        let decoded =
+            Function::from_parameter_string(&[0x4a, 0, 0x0d, 0x04, 0x22, 0x04, 0x00, 0x32, 0])
+                .unwrap();
+        assert!(matches!(decoded.button, MouseButton::Side11));
+        let Action::Keyboard(key_press) = decoded.action else {
+            panic!("expected a keyboard action, got {:?}", decoded.action);
+        };
+        assert!(matches!(key_press.key, UsbKbScanCode::KbA));
+        assert!(matches!(
+            key_press.modifiers.as_slice(),
+            [KeyMod::LShift, KeyMod::RShift]
+        ));
+        assert_eq!(key_press.interval_ms, 50);
+    }
+
+    #[test]
+    fn decode_unknown_bytes() {
+        assert_eq!(
+            Function::from_parameter_string(&[0x50, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::Button(0x50))
+        );
+        assert_eq!(
+            Function::from_parameter_string(&[0x40, 0, 0x02, 0x02, 0, 0xa5, 0, 0, 0]),
+            Err(DecodeError::Key(0xa5))
+        );
+        assert_eq!(
+            Function::from_parameter_string(&[0x40, 0, 0x06, 0x01, 0x03, 0, 0, 0, 0]),
+            Err(DecodeError::Sensitivity(0x03))
+        );
+        assert_eq!(
+            Function::from_parameter_string(&[0x40, 0, 0x07, 0x01, 0, 0, 0, 0, 0]),
+            Err(DecodeError::ActionType {
+                kind: 0x07,
+                len: 0x01
+            })
+        );
+        assert_eq!(
+            RazerReport::new(0x04, 0x05, &[0; 7]).to_function(),
+            Err(DecodeError::Command {
+                class: 0x04,
+                id: 0x05
+            })
+        );
+    }
+
+    #[test]
+    fn human_readable() {
+        let func = Function {
+            button: MouseButton::Side12,
+            layer: Layer::Base,
+            action: Action::Keyboard(KeyPress {
+                key: UsbKbScanCode::KbS,
+                modifiers: vec![KeyMod::LShift, KeyMod::LGui],
+                interval_ms: 50,
+            }),
+        };
+        assert_eq!(func.to_string(), "Side12: LShift+LGui+KbS every 50 ms");
+
+        let func = Function {
+            button: MouseButton::SenStageDown,
+            layer: Layer::Base,
+            action: Action::Sensitivity(SensitivityFunction::Clutch(SensitivityClutch {
+                x: 800,
+                y: 400,
+            })),
+        };
+        assert_eq!(
+            func.to_string(),
+            "SenStageDown: sensitivity clutch at 800x400 DPI"
+        );
+    }
+
+    #[test]
+    fn scan_code_from_byte() {
+        let mut decoded = 0;
+        for value in 0..=u8::MAX {
+            if let Ok(key) = UsbKbScanCode::try_from(value) {
+                assert_eq!(key as u8, value);
+                decoded += 1;
+            }
+        }
+        assert_eq!(decoded, UsbKbScanCode::ALL.len());
+        assert!(UsbKbScanCode::try_from(0xa5).is_err());
+        assert!(matches!(
+            UsbKbScanCode::try_from(0xe7),
+            Ok(UsbKbScanCode::KbRGUI)
+        ));
+    }
 }
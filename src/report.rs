@@ -0,0 +1,473 @@
+use crate::model::{DecodeError, Function, Layer, MouseButton, Profile};
+use crate::settings::{Dpi, DpiStages, PollingRate, MAX_DPI_STAGES};
+use std::fmt;
+
+/// Length of a report as handed to hidapi, including the leading report ID byte.
+pub(crate) const REPORT_LEN: usize = 91;
+/// Number of argument bytes a report has room for.
+pub(crate) const ARGUMENTS_LEN: usize = 80;
+
+/// The wireless naga uses 0x1f, most wired razer devices seem to use 0xff or 0x3f instead. Nobody has checked what the
+/// naga wants over a cable or bluetooth, openrazer uses 0x1f for the rest of the naga v2 line whichever way it's attached.
+const TRANSACTION_ID: u8 = 0x1f;
+
+/// Status byte of a report. Anything sent to the mouse is `New`, the response says what became of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum ReportStatus {
+    New = 0x00,
+    Busy = 0x01,
+    Success = 0x02,
+    Failure = 0x03,
+    /// This is what comes back when the mouse is asleep (or otherwise out of reach of the dongle.)
+    Timeout = 0x04,
+    NotSupported = 0x05,
+}
+
+impl TryFrom<u8> for ReportStatus {
+    type Error = ReportError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(ReportStatus::New),
+            0x01 => Ok(ReportStatus::Busy),
+            0x02 => Ok(ReportStatus::Success),
+            0x03 => Ok(ReportStatus::Failure),
+            0x04 => Ok(ReportStatus::Timeout),
+            0x05 => Ok(ReportStatus::NotSupported),
+            _ => Err(ReportError::Status(value)),
+        }
+    }
+}
+
+impl fmt::Display for ReportStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReportStatus::New => "the mouse hasn't gotten around to the command yet",
+            ReportStatus::Busy => "the mouse is busy",
+            ReportStatus::Success => "success",
+            ReportStatus::Failure => "the mouse rejected the command",
+            ReportStatus::Timeout => "the mouse didn't respond. Make sure your mouse isn't asleep",
+            ReportStatus::NotSupported => "the mouse doesn't support this command",
+        })
+    }
+}
+
+/// One feature report as exchanged with the mouse. Byte numbers below are positions in the buffer given to hidapi, so
+/// they're all off by one from the actual usb message because of the report ID at position 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RazerReport {
+    /// 1: Zero on a send report, on a get report it's a status indicator.
+    pub(crate) status: ReportStatus,
+    /// 2: Needs to match between request and response. Not covered by the checksum.
+    pub(crate) transaction_id: u8,
+    /// 3-4: Always seems to be zero for anything this tool sends.
+    pub(crate) remaining_packets: u16,
+    /// 5: Always seems to be zero as well.
+    pub(crate) protocol_type: u8,
+    /// 6: How many of the argument bytes are actually meaningful.
+    pub(crate) data_size: u8,
+    /// 7: Which group of commands this report belongs to, e.g. 0x02 for button assignment.
+    pub(crate) command_class: u8,
+    /// 8: The command within that class. Reading something back is generally the same ID with the top bit set.
+    pub(crate) command_id: u8,
+    /// 9-88: The payload.
+    pub(crate) arguments: [u8; ARGUMENTS_LEN],
+    /// 89: XOR of bytes 3 through 88. If this doesn't match, the mouse considers the message corrupted.
+    pub(crate) checksum: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ReportError {
+    Length(usize),
+    Checksum { expected: u8, actual: u8 },
+    DataSize(u8),
+    Status(u8),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::Length(len) => {
+                write!(
+                    f,
+                    "Expected a {} byte report, got {} bytes",
+                    REPORT_LEN, len
+                )
+            }
+            ReportError::Checksum { expected, actual } => write!(
+                f,
+                "Bad report checksum: expected {:#04x}, got {:#04x}",
+                expected, actual
+            ),
+            ReportError::DataSize(size) => write!(
+                f,
+                "Report claims {} argument bytes, but there's only room for {}",
+                size, ARGUMENTS_LEN
+            ),
+            ReportError::Status(status) => write!(f, "Unknown report status {:#04x}", status),
+        }
+    }
+}
+
+impl RazerReport {
+    pub(crate) fn new(command_class: u8, command_id: u8, arguments: &[u8]) -> Self {
+        assert!(arguments.len() <= ARGUMENTS_LEN);
+        let mut report = Self {
+            status: ReportStatus::New,
+            transaction_id: TRANSACTION_ID,
+            remaining_packets: 0,
+            protocol_type: 0,
+            data_size: arguments.len() as u8,
+            command_class,
+            command_id,
+            arguments: [0u8; ARGUMENTS_LEN],
+            checksum: 0,
+        };
+        report.arguments[..arguments.len()].clone_from_slice(arguments);
+        report.checksum = report.calculate_checksum();
+        report
+    }
+
+    /// Button assignment report. The first argument byte is the profile it goes to, with the function's parameter string
+    /// following it.
+    pub(crate) fn from_function(func: &Function, profile: Profile) -> Self {
+        let mut arguments = [0u8; 10];
+        arguments[0] = profile.byte();
+        arguments[1..].clone_from_slice(&func.generate_parameter_string());
+        Self::new(0x02, 0x0c, &arguments)
+    }
+
+    /// Asks for whatever the button is currently assigned to. The response carries the same layout as `from_function`.
+    pub(crate) fn get_binding(button: MouseButton, layer: Layer, profile: Profile) -> Self {
+        let mut arguments = [0u8; 10];
+        arguments[0] = profile.byte();
+        arguments[1] = button as u8;
+        arguments[2] = layer as u8;
+        Self::new(0x02, 0x8c, &arguments)
+    }
+
+    /// Switches which onboard profile the mouse is using. Not one I've captured from Synapse; this is the layout other
+    /// Razer mice with onboard profiles seem to use, a single byte with the slot number.
+    pub(crate) fn set_active_profile(profile: Profile) -> Self {
+        Self::new(0x05, 0x02, &[profile.byte()])
+    }
+
+    /// Asks which onboard profile is in use. The response carries it the same way `set_active_profile` sends it.
+    pub(crate) fn get_active_profile() -> Self {
+        Self::new(0x05, 0x82, &[0x00])
+    }
+
+    /// Decodes an active profile report, whether it's one we sent or one the mouse sent back.
+    pub(crate) fn to_active_profile(&self) -> Result<Profile, DecodeError> {
+        if self.command_class != 0x05 || self.command_id & 0x7f != 0x02 {
+            return Err(DecodeError::Command {
+                class: self.command_class,
+                id: self.command_id,
+            });
+        }
+        Profile::try_from(self.arguments[0])
+    }
+
+    /// Firmware version, major and minor in the first two argument bytes of the response.
+    pub(crate) fn get_firmware_version() -> Self {
+        Self::new(0x00, 0x81, &[0x00, 0x00])
+    }
+
+    /// Serial number, as up to 22 ASCII characters padded with zeroes.
+    pub(crate) fn get_serial() -> Self {
+        Self::new(0x00, 0x82, &[0u8; 22])
+    }
+
+    /// Device mode in the first argument byte of the response. See `DeviceMode`.
+    pub(crate) fn get_device_mode() -> Self {
+        Self::new(0x00, 0x84, &[0x00, 0x00])
+    }
+
+    /// Battery level, 0 to 255 in the second argument byte of the response. Same as other wireless Razer mice.
+    pub(crate) fn get_battery_level() -> Self {
+        Self::new(0x07, 0x80, &[0x00, 0x00])
+    }
+
+    /// Whether the mouse is charging, 0 or 1 in the second argument byte of the response.
+    pub(crate) fn get_charging_status() -> Self {
+        Self::new(0x07, 0x84, &[0x00, 0x00])
+    }
+
+    /// How long the mouse waits before going to sleep, in seconds as a big endian u16.
+    pub(crate) fn set_idle_time(seconds: u16) -> Self {
+        Self::new(0x07, 0x03, &seconds.to_be_bytes())
+    }
+
+    pub(crate) fn get_idle_time() -> Self {
+        Self::new(0x07, 0x83, &[0x00, 0x00])
+    }
+
+    /// Battery level the mouse goes into low power mode at. Same 0 to 255 scale as `get_battery_level`.
+    pub(crate) fn set_low_battery_threshold(raw: u8) -> Self {
+        Self::new(0x07, 0x01, &[raw])
+    }
+
+    pub(crate) fn get_low_battery_threshold() -> Self {
+        Self::new(0x07, 0x81, &[0x00])
+    }
+
+    /// Same layout as on other Razer mice, a single byte. See `PollingRate` for what it means.
+    pub(crate) fn set_polling_rate(rate: PollingRate) -> Self {
+        Self::new(0x00, 0x05, &[rate.byte()])
+    }
+
+    pub(crate) fn get_polling_rate() -> Self {
+        Self::new(0x00, 0x85, &[0x00])
+    }
+
+    /// Decodes a polling rate report, whether it's one we sent or one the mouse sent back.
+    pub(crate) fn to_polling_rate(&self) -> Result<PollingRate, DecodeError> {
+        if self.command_class != 0x00 || self.command_id & 0x7f != 0x05 {
+            return Err(DecodeError::Command {
+                class: self.command_class,
+                id: self.command_id,
+            });
+        }
+        PollingRate::from_byte(self.arguments[0]).ok_or(DecodeError::PollingRate(self.arguments[0]))
+    }
+
+    /// Sets the sensitivity right away, independent of the stages. Also going by other Razer mice: profile, then X and Y
+    /// as big endian u16s, then 2 zero bytes.
+    pub(crate) fn set_dpi(dpi: Dpi, profile: Profile) -> Self {
+        let mut arguments = vec![profile.byte()];
+        arguments.extend(dpi.x.to_be_bytes());
+        arguments.extend(dpi.y.to_be_bytes());
+        arguments.extend([0, 0]);
+        Self::new(0x04, 0x05, &arguments)
+    }
+
+    pub(crate) fn get_dpi(profile: Profile) -> Self {
+        Self::new(0x04, 0x85, &[profile.byte(), 0, 0, 0, 0, 0, 0])
+    }
+
+    /// Decodes a current DPI report, whether it's one we sent or one the mouse sent back.
+    pub(crate) fn to_dpi(&self) -> Result<Dpi, DecodeError> {
+        if self.command_class != 0x04 || self.command_id & 0x7f != 0x05 {
+            return Err(DecodeError::Command {
+                class: self.command_class,
+                id: self.command_id,
+            });
+        }
+        Ok(Dpi {
+            x: u16::from_be_bytes([self.arguments[1], self.arguments[2]]),
+            y: u16::from_be_bytes([self.arguments[3], self.arguments[4]]),
+        })
+    }
+
+    /// DPI stage table. Going by other Razer mice, since Synapse doesn't seem to send this on its own: profile, active
+    /// stage, number of stages, then 7 bytes for each stage (stage number, X and Y as big endian u16s, 2 zero bytes.)
+    pub(crate) fn set_dpi_stages(stages: &DpiStages, profile: Profile) -> Self {
+        let mut arguments = vec![profile.byte(), stages.active, stages.stages.len() as u8];
+        for (i, stage) in stages.stages.iter().enumerate() {
+            arguments.push(i as u8 + 1);
+            arguments.extend(stage.x.to_be_bytes());
+            arguments.extend(stage.y.to_be_bytes());
+            arguments.extend([0, 0]);
+        }
+        Self::new(0x04, 0x06, &arguments)
+    }
+
+    /// Asks for the DPI stage table. The request is sized for the biggest table so the response fits.
+    pub(crate) fn get_dpi_stages(profile: Profile) -> Self {
+        let mut arguments = [0u8; 3 + 7 * MAX_DPI_STAGES];
+        arguments[0] = profile.byte();
+        Self::new(0x04, 0x86, &arguments)
+    }
+
+    /// Decodes a DPI stage table report, whether it's one we sent or one the mouse sent back.
+    pub(crate) fn to_dpi_stages(&self) -> Result<DpiStages, DecodeError> {
+        if self.command_class != 0x04 || self.command_id & 0x7f != 0x06 {
+            return Err(DecodeError::Command {
+                class: self.command_class,
+                id: self.command_id,
+            });
+        }
+        let count = self.arguments[2];
+        if count as usize > MAX_DPI_STAGES {
+            return Err(DecodeError::DpiStages(count));
+        }
+        let stages = self.arguments[3..]
+            .chunks(7)
+            .take(count as usize)
+            .map(|stage| Dpi {
+                x: u16::from_be_bytes([stage[1], stage[2]]),
+                y: u16::from_be_bytes([stage[3], stage[4]]),
+            })
+            .collect();
+        Ok(DpiStages {
+            active: self.arguments[1],
+            stages,
+        })
+    }
+
+    /// Decodes a button assignment report, whether it's one we sent or one the mouse sent back.
+    pub(crate) fn to_function(&self) -> Result<Function, DecodeError> {
+        if self.command_class != 0x02 || self.command_id & 0x7f != 0x0c {
+            return Err(DecodeError::Command {
+                class: self.command_class,
+                id: self.command_id,
+            });
+        }
+        let mut string = [0u8; 9];
+        string.clone_from_slice(&self.arguments[1..10]);
+        Function::from_parameter_string(&string)
+    }
+
+    /// Parses a buffer as received from hidapi, report ID included, and verifies its checksum.
+    pub(crate) fn parse(buf: &[u8]) -> Result<Self, ReportError> {
+        if buf.len() != REPORT_LEN {
+            return Err(ReportError::Length(buf.len()));
+        }
+        if buf[6] as usize > ARGUMENTS_LEN {
+            return Err(ReportError::DataSize(buf[6]));
+        }
+        let mut arguments = [0u8; ARGUMENTS_LEN];
+        arguments.clone_from_slice(&buf[9..89]);
+        let report = Self {
+            status: ReportStatus::try_from(buf[1])?,
+            transaction_id: buf[2],
+            remaining_packets: u16::from_be_bytes([buf[3], buf[4]]),
+            protocol_type: buf[5],
+            data_size: buf[6],
+            command_class: buf[7],
+            command_id: buf[8],
+            arguments,
+            checksum: buf[89],
+        };
+        let expected = report.calculate_checksum();
+        if expected != report.checksum {
+            return Err(ReportError::Checksum {
+                expected,
+                actual: report.checksum,
+            });
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn calculate_checksum(&self) -> u8 {
+        self.to_bytes()[3..89].iter().fold(0, |acc, x| acc ^ x)
+    }
+
+    /// The meaningful part of the payload, as given by the data size.
+    pub(crate) fn args(&self) -> &[u8] {
+        &self.arguments[..(self.data_size as usize).min(ARGUMENTS_LEN)]
+    }
+
+    /// Whether `response` is the answer to this report. Responses to a write echo the whole payload back, responses to a
+    /// read (top bit of the command ID set) carry whatever was asked for instead, so only the command has to match.
+    pub(crate) fn answered_by(&self, response: &RazerReport) -> bool {
+        self.transaction_id == response.transaction_id
+            && self.command_class == response.command_class
+            && self.command_id == response.command_id
+            && (self.is_read() || self.args() == response.args())
+    }
+
+    pub(crate) fn is_read(&self) -> bool {
+        self.command_id & 0x80 != 0
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; REPORT_LEN] {
+        let mut message = [0u8; REPORT_LEN];
+        // 0: Report ID: Needed strictly for the API, doesn't actually get sent to the mouse.
+        message[0] = 0;
+        message[1] = self.status as u8;
+        message[2] = self.transaction_id;
+        message[3..5].clone_from_slice(&self.remaining_packets.to_be_bytes());
+        message[5] = self.protocol_type;
+        message[6] = self.data_size;
+        message[7] = self.command_class;
+        message[8] = self.command_id;
+        message[9..89].clone_from_slice(&self.arguments);
+        message[89] = self.checksum;
+        // 90: Always zero.
+        message
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RazerReport, ReportError, ReportStatus, REPORT_LEN};
+    use crate::model::{generate_message, Action, Function, Layer, MouseButton, Profile};
+    use crate::settings::{Dpi, DpiStages};
+
+    #[test]
+    fn parse_round_trip() {
+        let func = Function {
+            button: MouseButton::Side1,
+            layer: Layer::Base,
+            action: Action::Hypershift,
+        };
+        let report = RazerReport::from_function(&func, Profile::default());
+        assert_eq!(report.data_size, 10);
+        assert_eq!(report.arguments[0], 0x01);
+        assert_eq!(
+            RazerReport::from_function(&func, Profile::Volatile).arguments[0],
+            0x00
+        );
+        assert_eq!(report.to_bytes(), generate_message(&func));
+        assert_eq!(RazerReport::parse(&report.to_bytes()), Ok(report));
+    }
+
+    #[test]
+    fn parse_rejects_bad_checksum() {
+        let mut bytes = RazerReport::new(0x02, 0x0c, &[0x01, 0x40]).to_bytes();
+        bytes[10] = 0x41;
+        assert_eq!(
+            RazerReport::parse(&bytes),
+            Err(ReportError::Checksum {
+                expected: 0x4c,
+                actual: 0x4d,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_status() {
+        let mut bytes = RazerReport::new(0x02, 0x0c, &[0x01, 0x40]).to_bytes();
+        bytes[1] = 0x05;
+        assert_eq!(
+            RazerReport::parse(&bytes).map(|r| r.status),
+            Ok(ReportStatus::NotSupported)
+        );
+        bytes[1] = 0x06;
+        assert_eq!(RazerReport::parse(&bytes), Err(ReportError::Status(0x06)));
+    }
+
+    #[test]
+    fn parse_rejects_short_buffer() {
+        assert_eq!(RazerReport::parse(&[0u8; 90]), Err(ReportError::Length(90)));
+        let mut bytes = [0u8; REPORT_LEN];
+        bytes[6] = 81;
+        assert_eq!(RazerReport::parse(&bytes), Err(ReportError::DataSize(81)));
+    }
+
+    #[test]
+    fn dpi_stages_round_trip() {
+        let stages = DpiStages {
+            active: 2,
+            stages: vec![Dpi { x: 400, y: 400 }, Dpi { x: 30000, y: 100 }],
+        };
+        let report = RazerReport::set_dpi_stages(&stages, Profile::default());
+        assert_eq!(report.data_size, 17);
+        assert_eq!(
+            report.args(),
+            [1, 2, 2, 1, 0x01, 0x90, 0x01, 0x90, 0, 0, 2, 0x75, 0x30, 0x00, 0x64, 0, 0]
+        );
+        assert_eq!(report.to_dpi_stages(), Ok(stages));
+    }
+
+    #[test]
+    fn dpi_round_trip() {
+        let dpi = Dpi { x: 30000, y: 100 };
+        let report = RazerReport::set_dpi(dpi, Profile::Volatile);
+        assert_eq!(report.args(), [0, 0x75, 0x30, 0x00, 0x64, 0, 0]);
+        assert_eq!(report.to_dpi(), Ok(dpi));
+        assert!(RazerReport::get_dpi(Profile::Volatile).is_read());
+    }
+}
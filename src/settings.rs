@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Synapse allows 100 to 30000, same as for the sensitivity clutch.
+pub(crate) const MIN_DPI: u16 = 100;
+pub(crate) const MAX_DPI: u16 = 30000;
+/// Synapse only lets you have up to 5 stages. The report has room for more, but I haven't tried what the mouse does with
+/// them.
+pub(crate) const MAX_DPI_STAGES: usize = 5;
+/// Synapse's idle timer slider goes from 1 to 15 minutes.
+pub(crate) const MIN_IDLE_SECONDS: u16 = 60;
+pub(crate) const MAX_IDLE_SECONDS: u16 = 900;
+/// Synapse's low power slider goes from 5% to 100%.
+pub(crate) const MIN_LOW_BATTERY_PERCENT: u8 = 5;
+pub(crate) const MAX_LOW_BATTERY_PERCENT: u8 = 100;
+
+/// Sensitivity along each axis. Sent as big endian u16s, the same as `SensitivityClutch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct Dpi {
+    pub(crate) x: u16,
+    pub(crate) y: u16,
+}
+
+impl Dpi {
+    pub(crate) fn validate(&self) -> Result<(), SettingError> {
+        for value in [self.x, self.y] {
+            if !(MIN_DPI..=MAX_DPI).contains(&value) {
+                return Err(SettingError::Dpi(value));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Dpi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{} DPI", self.x, self.y)
+    }
+}
+
+/// The sensitivities the stage buttons step through.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct DpiStages {
+    /// Which of the stages the mouse is on, counting from 1.
+    pub(crate) active: u8,
+    pub(crate) stages: Vec<Dpi>,
+}
+
+impl DpiStages {
+    pub(crate) fn validate(&self) -> Result<(), SettingError> {
+        if self.stages.is_empty() || self.stages.len() > MAX_DPI_STAGES {
+            return Err(SettingError::StageCount(self.stages.len()));
+        }
+        if self.active == 0 || self.active as usize > self.stages.len() {
+            return Err(SettingError::ActiveStage(self.active));
+        }
+        self.stages.iter().try_for_each(Dpi::validate)
+    }
+}
+
+impl fmt::Display for DpiStages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let marker = if i + 1 == self.active as usize {
+                "*"
+            } else {
+                " "
+            };
+            write!(f, "{} {}: {}", marker, i + 1, stage)?;
+        }
+        Ok(())
+    }
+}
+
+/// How often the mouse reports movement. Goes by the same byte other Razer mice use, which is the number of
+/// milliseconds between reports at 1000 Hz. The HyperSpeed dongle doesn't seem to go any faster than 1000 Hz.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub(crate) enum PollingRate {
+    Hz125,
+    Hz250,
+    Hz500,
+    Hz1000,
+}
+
+impl PollingRate {
+    pub(crate) const ALL: [PollingRate; 4] = [
+        PollingRate::Hz125,
+        PollingRate::Hz250,
+        PollingRate::Hz500,
+        PollingRate::Hz1000,
+    ];
+
+    pub(crate) fn hz(self) -> u16 {
+        match self {
+            PollingRate::Hz125 => 125,
+            PollingRate::Hz250 => 250,
+            PollingRate::Hz500 => 500,
+            PollingRate::Hz1000 => 1000,
+        }
+    }
+
+    pub(crate) fn byte(self) -> u8 {
+        (1000 / self.hz()) as u8
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        PollingRate::ALL
+            .into_iter()
+            .find(|rate| rate.byte() == byte)
+    }
+}
+
+impl TryFrom<u16> for PollingRate {
+    type Error = SettingError;
+
+    fn try_from(hz: u16) -> Result<Self, Self::Error> {
+        PollingRate::ALL
+            .into_iter()
+            .find(|rate| rate.hz() == hz)
+            .ok_or(SettingError::PollingRate(hz))
+    }
+}
+
+impl From<PollingRate> for u16 {
+    fn from(rate: PollingRate) -> Self {
+        rate.hz()
+    }
+}
+
+impl fmt::Display for PollingRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", self.hz())
+    }
+}
+
+/// Not really a setting, but it comes from the same kind of report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct Battery {
+    pub(crate) percent: u8,
+    pub(crate) charging: bool,
+}
+
+impl Battery {
+    pub(crate) fn from_raw(level: u8, charging: bool) -> Self {
+        Self {
+            percent: percent_from_raw(level),
+            charging,
+        }
+    }
+}
+
+/// Battery levels go over the wire as 0 to 255 rather than a percentage.
+pub(crate) fn percent_from_raw(raw: u8) -> u8 {
+    ((raw as u16 * 100 + 127) / 255) as u8
+}
+
+pub(crate) fn raw_from_percent(percent: u8) -> u8 {
+    ((percent.min(100) as u16 * 255 + 50) / 100) as u8
+}
+
+pub(crate) fn validate_idle_seconds(seconds: u16) -> Result<(), SettingError> {
+    if !(MIN_IDLE_SECONDS..=MAX_IDLE_SECONDS).contains(&seconds) {
+        return Err(SettingError::IdleSeconds(seconds));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_low_battery_percent(percent: u8) -> Result<(), SettingError> {
+    if !(MIN_LOW_BATTERY_PERCENT..=MAX_LOW_BATTERY_PERCENT).contains(&percent) {
+        return Err(SettingError::LowBatteryPercent(percent));
+    }
+    Ok(())
+}
+
+impl fmt::Display for Battery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.percent)?;
+        if self.charging {
+            write!(f, ", charging")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FirmwareVersion {
+    pub(crate) major: u8,
+    pub(crate) minor: u8,
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{:02}", self.major, self.minor)
+    }
+}
+
+/// What the mouse thinks it's being driven by. Synapse switches it to driver mode while it's running, which is
+/// presumably why some buttons act differently with Synapse open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DeviceMode {
+    Normal,
+    Factory,
+    Driver,
+    Unknown(u8),
+}
+
+impl From<u8> for DeviceMode {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0x00 => DeviceMode::Normal,
+            0x02 => DeviceMode::Factory,
+            0x03 => DeviceMode::Driver,
+            other => DeviceMode::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for DeviceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceMode::Normal => write!(f, "normal"),
+            DeviceMode::Factory => write!(f, "factory"),
+            DeviceMode::Driver => write!(f, "driver"),
+            DeviceMode::Unknown(byte) => write!(f, "unknown ({:#04x})", byte),
+        }
+    }
+}
+
+/// A setting that's outside of what the mouse accepts.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SettingError {
+    Dpi(u16),
+    StageCount(usize),
+    ActiveStage(u8),
+    PollingRate(u16),
+    IdleSeconds(u16),
+    LowBatteryPercent(u8),
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingError::Dpi(dpi) => write!(
+                f,
+                "{} DPI is out of range, it has to be {} to {}",
+                dpi, MIN_DPI, MAX_DPI
+            ),
+            SettingError::StageCount(count) => write!(
+                f,
+                "There have to be 1 to {} DPI stages, not {}",
+                MAX_DPI_STAGES, count
+            ),
+            SettingError::ActiveStage(stage) => {
+                write!(f, "Active DPI stage {} isn't one of the stages", stage)
+            }
+            SettingError::PollingRate(hz) => write!(
+                f,
+                "Polling rate can't be {} Hz, it has to be one of {}",
+                hz,
+                PollingRate::ALL
+                    .map(|rate| rate.hz().to_string())
+                    .join(", ")
+            ),
+            SettingError::IdleSeconds(seconds) => write!(
+                f,
+                "Idle time can't be {} seconds, it has to be {} to {}",
+                seconds, MIN_IDLE_SECONDS, MAX_IDLE_SECONDS
+            ),
+            SettingError::LowBatteryPercent(percent) => write!(
+                f,
+                "Low battery threshold can't be {}%, it has to be {}% to {}%",
+                percent, MIN_LOW_BATTERY_PERCENT, MAX_LOW_BATTERY_PERCENT
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        percent_from_raw, raw_from_percent, validate_idle_seconds, validate_low_battery_percent,
+        Battery, Dpi, DpiStages, PollingRate, SettingError,
+    };
+
+    #[test]
+    fn validate_stages() {
+        let mut stages = DpiStages {
+            active: 2,
+            stages: vec![Dpi { x: 800, y: 800 }, Dpi { x: 1600, y: 1200 }],
+        };
+        assert_eq!(stages.validate(), Ok(()));
+        assert_eq!(stages.to_string(), "  1: 800x800 DPI\n* 2: 1600x1200 DPI");
+
+        stages.active = 3;
+        assert_eq!(stages.validate(), Err(SettingError::ActiveStage(3)));
+        stages.active = 1;
+        stages.stages[1].y = 30001;
+        assert_eq!(stages.validate(), Err(SettingError::Dpi(30001)));
+        stages.stages = vec![Dpi { x: 800, y: 800 }; 6];
+        assert_eq!(stages.validate(), Err(SettingError::StageCount(6)));
+        stages.stages.clear();
+        assert_eq!(stages.validate(), Err(SettingError::StageCount(0)));
+    }
+
+    #[test]
+    fn polling_rate() {
+        assert_eq!(PollingRate::Hz1000.byte(), 0x01);
+        assert_eq!(PollingRate::Hz125.byte(), 0x08);
+        assert_eq!(PollingRate::from_byte(0x02), Some(PollingRate::Hz500));
+        assert_eq!(PollingRate::from_byte(0x03), None);
+        assert_eq!(PollingRate::try_from(250), Ok(PollingRate::Hz250));
+        assert_eq!(
+            PollingRate::try_from(2000).unwrap_err().to_string(),
+            "Polling rate can't be 2000 Hz, it has to be one of 125, 250, 500, 1000"
+        );
+        assert_eq!(ron::from_str("500"), Ok(PollingRate::Hz500));
+        assert!(ron::from_str::<PollingRate>("600").is_err());
+    }
+
+    #[test]
+    fn battery() {
+        assert_eq!(Battery::from_raw(255, false).percent, 100);
+        assert_eq!(Battery::from_raw(0, false).percent, 0);
+        assert_eq!(Battery::from_raw(128, true).to_string(), "50%, charging");
+        assert_eq!(
+            serde_json::to_string(&Battery::from_raw(64, false)).unwrap(),
+            r#"{"percent":25,"charging":false}"#
+        );
+    }
+
+    #[test]
+    fn power_settings() {
+        for percent in 0..=100 {
+            assert_eq!(percent_from_raw(raw_from_percent(percent)), percent);
+        }
+        assert_eq!(raw_from_percent(15), 38);
+        assert_eq!(validate_idle_seconds(60), Ok(()));
+        assert_eq!(
+            validate_idle_seconds(901),
+            Err(SettingError::IdleSeconds(901))
+        );
+        assert_eq!(validate_low_battery_percent(5), Ok(()));
+        assert_eq!(
+            validate_low_battery_percent(4),
+            Err(SettingError::LowBatteryPercent(4))
+        );
+    }
+}
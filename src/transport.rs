@@ -0,0 +1,76 @@
+use hidapi::{HidDevice, HidResult};
+
+/// Anything that can exchange feature reports with the mouse. The real thing goes through hidapi, but keeping the apply
+/// logic behind this trait means it can be exercised without a physical mouse plugged in.
+pub(crate) trait FeatureReportDevice {
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()>;
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize>;
+}
+
+impl FeatureReportDevice for HidDevice {
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()> {
+        HidDevice::send_feature_report(self, data)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        HidDevice::get_feature_report(self, buf)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::FeatureReportDevice;
+    use hidapi::{HidError, HidResult};
+    use std::{cell::RefCell, collections::VecDeque};
+
+    /// Records every report sent to it and hands back whatever responses were scripted, in order.
+    #[derive(Default)]
+    pub(crate) struct MockDevice {
+        pub(crate) sent: RefCell<Vec<Vec<u8>>>,
+        responses: RefCell<VecDeque<Vec<u8>>>,
+        reject_sends: bool,
+    }
+
+    impl MockDevice {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// A device that errors on every send, like the wrong interface of the right mouse would.
+        pub(crate) fn rejecting() -> Self {
+            Self {
+                reject_sends: true,
+                ..Self::default()
+            }
+        }
+
+        pub(crate) fn push_response(&self, response: &[u8]) {
+            self.responses.borrow_mut().push_back(response.to_vec());
+        }
+    }
+
+    impl FeatureReportDevice for MockDevice {
+        fn send_feature_report(&self, data: &[u8]) -> HidResult<()> {
+            if self.reject_sends {
+                return Err(HidError::HidApiError {
+                    message: "mock device rejected the report".into(),
+                });
+            }
+            self.sent.borrow_mut().push(data.to_vec());
+            Ok(())
+        }
+
+        fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+            let response =
+                self.responses
+                    .borrow_mut()
+                    .pop_front()
+                    .ok_or_else(|| HidError::HidApiError {
+                        message: "mock device has no scripted response left".into(),
+                    })?;
+            let len = response.len().min(buf.len());
+            buf[..len].copy_from_slice(&response[..len]);
+            Ok(len)
+        }
+    }
+}